@@ -9,7 +9,7 @@ async fn main() {
 
     srv.middleware(AccessLog);
 
-    srv.get("/", |_req| async move {
+    srv.get("/", |_req: RequestCtx| async move {
         hyper::Response::builder()
             .status(hyper::StatusCode::OK)
             .body(hyper::Body::from("Welcome!"))
@@ -0,0 +1,506 @@
+//! Extractors, typed parts of a request a handler takes as arguments:
+//!
+//! ```no_run
+//! # use tinyweb::{Json, Path, Response, Server};
+//! # #[derive(serde::Deserialize)]
+//! # struct NewUser {}
+//! async fn create(Path(org): Path<u64>, Json(user): Json<NewUser>) -> Response {
+//!     // ...
+//! #   unimplemented!()
+//! }
+//!
+//! # let mut srv = Server::new();
+//! srv.post("/orgs/{org:u64}/users", create);
+//! ```
+//!
+//! A handler takes either the whole [`RequestCtx`] or up to eight extractors,
+//! and returns anything implementing [`IntoResponse`]. When an extractor
+//! fails its rejection is sent and the handler does not run.
+//!
+//! Since a handler may take any of these, the argument types of a closure
+//! can no longer be inferred and have to be written out, `|_req| ...` fails
+//! with E0282 where `|_req: RequestCtx| ...` compiles.
+
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+
+use crate::{
+    BoxHTTPHandler, Error, FormError, HTTPHandler, IntoResponse, JsonError, Multipart,
+    MultipartError, ParamError, Params, QueryError, RequestCtx, Response,
+};
+
+#[async_trait::async_trait]
+pub trait FromRequest: Sized {
+    /// What is sent instead of running the handler when extracting fails.
+    type Rejection: IntoResponse;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Self::Rejection>;
+}
+
+/// Path parameters deserialized into `T`: a single value, a tuple of the
+/// parameters in order or a struct with fields named after them.
+#[derive(Debug)]
+pub struct Path<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Path<T> {
+    type Rejection = Error;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Error> {
+        let params = &ctx.params;
+        serde_path_to_error::deserialize(ParamsDeserializer(params))
+            .map(Path)
+            .map_err(|err| {
+                let name = match err.path().iter().next() {
+                    Some(serde_path_to_error::Segment::Map { key }) => key.clone(),
+                    Some(serde_path_to_error::Segment::Seq { index }) => params
+                        .iter()
+                        .nth(*index)
+                        .map_or("", |(name, _)| name)
+                        .to_string(),
+                    _ => params
+                        .iter()
+                        .next()
+                        .map_or("", |(name, _)| name)
+                        .to_string(),
+                };
+
+                match err.into_inner() {
+                    PathError::Missing(name) => ParamError::Missing(name.to_string()).into(),
+                    PathError::Invalid { value, reason } => ParamError::Invalid {
+                        name,
+                        value,
+                        reason,
+                    }
+                    .into(),
+                    PathError::Mismatch(reason) => Error::new(format!(
+                        "path parameters do not fit the handler: {}",
+                        reason
+                    )),
+                }
+            })
+    }
+}
+
+/// The query string deserialized into `T`, see [`RequestCtx::query`].
+#[derive(Debug)]
+pub struct Query<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Query<T> {
+    type Rejection = QueryError;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, QueryError> {
+        ctx.query().map(Query)
+    }
+}
+
+/// A JSON body deserialized into `T`, see [`RequestCtx::json`].
+#[derive(Debug)]
+pub struct Json<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Json<T> {
+    type Rejection = JsonError;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, JsonError> {
+        ctx.json().await.map(Json)
+    }
+}
+
+/// A form body deserialized into `T`, see [`RequestCtx::form`].
+#[derive(Debug)]
+pub struct Form<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T: DeserializeOwned + Send> FromRequest for Form<T> {
+    type Rejection = FormError;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, FormError> {
+        ctx.form().await.map(Form)
+    }
+}
+
+#[async_trait::async_trait]
+impl FromRequest for Multipart {
+    type Rejection = MultipartError;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, MultipartError> {
+        ctx.multipart()
+    }
+}
+
+#[async_trait::async_trait]
+impl FromRequest for hyper::Method {
+    type Rejection = Infallible;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Infallible> {
+        Ok(ctx.request.method().clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl FromRequest for hyper::Uri {
+    type Rejection = Infallible;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Infallible> {
+        Ok(ctx.request.uri().clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl FromRequest for hyper::HeaderMap {
+    type Rejection = Infallible;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Infallible> {
+        Ok(ctx.request.headers().clone())
+    }
+}
+
+/// Application state registered with [`Server::state`], cloned out of the
+/// server. Wrap state that is expensive to clone in an `Arc`.
+///
+/// [`Server::state`]: crate::Server::state
+#[derive(Debug, Clone)]
+pub struct State<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T: Clone + Send + Sync + 'static> FromRequest for State<T> {
+    type Rejection = Error;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Error> {
+        match ctx.state::<T>() {
+            Some(state) => Ok(State(state.clone())),
+            None => Err(Error::new(format!(
+                "no application state of type {}",
+                std::any::type_name::<T>()
+            ))),
+        }
+    }
+}
+
+/// A value a middleware put into [`RequestCtx::extensions`], cloned out of
+/// them like [`State`].
+#[derive(Debug, Clone)]
+pub struct Extension<T>(pub T);
+
+#[async_trait::async_trait]
+impl<T: Clone + Send + Sync + 'static> FromRequest for Extension<T> {
+    type Rejection = Error;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Error> {
+        match ctx.extensions.get::<T>() {
+            Some(value) => Ok(Extension(value.clone())),
+            None => Err(Error::new(format!(
+                "no request extension of type {}",
+                std::any::type_name::<T>()
+            ))),
+        }
+    }
+}
+
+/// `None` instead of the rejection when extracting `T` fails.
+#[async_trait::async_trait]
+impl<T: FromRequest + Send> FromRequest for Option<T> {
+    type Rejection = Infallible;
+
+    async fn from_request(ctx: &mut RequestCtx) -> Result<Self, Infallible> {
+        Ok(T::from_request(ctx).await.ok())
+    }
+}
+
+/// Anything that can be registered as a route handler: an [`HTTPHandler`],
+/// such as a function taking the [`RequestCtx`], or a function taking
+/// extractors. `Args` only tells the implementations apart, which is also why
+/// closures need their argument types annotated.
+pub trait IntoHandler<Args> {
+    fn into_handler(self) -> BoxHTTPHandler;
+}
+
+impl<H: HTTPHandler> IntoHandler<RequestCtx> for H {
+    fn into_handler(self) -> BoxHTTPHandler {
+        Box::new(self)
+    }
+}
+
+/// A function taking extractors as an [`HTTPHandler`].
+struct ExtractHandler<F, Args> {
+    f: F,
+    _args: PhantomData<fn() -> Args>,
+}
+
+macro_rules! extract_handler {
+    ($($arg:ident),*) => {
+        impl<F, Fut, $($arg,)*> IntoHandler<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            $($arg: FromRequest + Send + 'static,)*
+        {
+            fn into_handler(self) -> BoxHTTPHandler {
+                Box::new(ExtractHandler {
+                    f: self,
+                    _args: PhantomData,
+                })
+            }
+        }
+
+        #[allow(non_snake_case, unused_mut, unused_variables)]
+        #[async_trait::async_trait]
+        impl<F, Fut, $($arg,)*> HTTPHandler for ExtractHandler<F, ($($arg,)*)>
+        where
+            F: Fn($($arg),*) -> Fut + Send + Sync + 'static,
+            Fut: Future + Send + 'static,
+            Fut::Output: IntoResponse,
+            $($arg: FromRequest + Send + 'static,)*
+        {
+            async fn handle(&self, mut ctx: RequestCtx) -> Response {
+                $(
+                    let $arg = match $arg::from_request(&mut ctx).await {
+                        Ok(value) => value,
+                        Err(rejection) => return rejection.into_response(),
+                    };
+                )*
+                (self.f)($($arg),*).await.into_response()
+            }
+        }
+    };
+}
+
+extract_handler!();
+extract_handler!(T1);
+extract_handler!(T1, T2);
+extract_handler!(T1, T2, T3);
+extract_handler!(T1, T2, T3, T4);
+extract_handler!(T1, T2, T3, T4, T5);
+extract_handler!(T1, T2, T3, T4, T5, T6);
+extract_handler!(T1, T2, T3, T4, T5, T6, T7);
+extract_handler!(T1, T2, T3, T4, T5, T6, T7, T8);
+
+/// Why path parameters could not be deserialized.
+#[derive(Debug)]
+enum PathError {
+    /// The handler expects a parameter the route does not have.
+    Missing(&'static str),
+    /// A parameter value which does not parse.
+    Invalid { value: String, reason: String },
+    /// The parameters do not fit the expected type otherwise.
+    Mismatch(String),
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Missing(name) => write!(f, "missing path parameter {:?}", name),
+            PathError::Invalid { value, reason } => {
+                write!(f, "invalid value {:?}: {}", value, reason)
+            }
+            PathError::Mismatch(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+impl de::Error for PathError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        PathError::Mismatch(msg.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        PathError::Missing(field)
+    }
+}
+
+/// Deserializes the parameters as a map, a sequence or a single value.
+struct ParamsDeserializer<'de>(&'de Params);
+
+impl<'de> ParamsDeserializer<'de> {
+    fn single(&self) -> Result<ValueDeserializer<'de>, PathError> {
+        let mut params = self.0.iter();
+        match (params.next(), params.next()) {
+            (Some((_, value)), None) => Ok(ValueDeserializer(value)),
+            _ => Err(PathError::Mismatch(format!(
+                "expected 1 path parameter, the route has {}",
+                self.0.len()
+            ))),
+        }
+    }
+}
+
+macro_rules! forward_to_single {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+                self.single()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ParamsDeserializer<'de> {
+    type Error = PathError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        let params = self
+            .0
+            .iter()
+            .map(|(name, value)| (name, ValueDeserializer(value)));
+        visitor.visit_map(de::value::MapDeserializer::new(params))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        let mut seq = de::value::SeqDeserializer::new(
+            self.0.iter().map(|(_, value)| ValueDeserializer(value)),
+        );
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        self.single()?.deserialize_newtype_struct(name, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        self.single()?.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_single! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_option
+        deserialize_unit deserialize_identifier deserialize_ignored_any
+    }
+}
+
+/// Deserializes a parameter value, parsing it as the expected type.
+struct ValueDeserializer<'de>(&'de str);
+
+macro_rules! parse_value {
+    ($($method:ident => $visit:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+                match self.0.parse() {
+                    Ok(value) => visitor.$visit(value),
+                    Err(e) => Err(PathError::Invalid {
+                        value: self.0.to_string(),
+                        reason: e.to_string(),
+                    }),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer<'de> {
+    type Error = PathError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        visitor.visit_borrowed_str(self.0)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, PathError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, PathError> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    parse_value! {
+        deserialize_bool => visit_bool
+        deserialize_i8 => visit_i8
+        deserialize_i16 => visit_i16
+        deserialize_i32 => visit_i32
+        deserialize_i64 => visit_i64
+        deserialize_i128 => visit_i128
+        deserialize_u8 => visit_u8
+        deserialize_u16 => visit_u16
+        deserialize_u32 => visit_u32
+        deserialize_u64 => visit_u64
+        deserialize_u128 => visit_u128
+        deserialize_f32 => visit_f32
+        deserialize_f64 => visit_f64
+        deserialize_char => visit_char
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, PathError> for ValueDeserializer<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
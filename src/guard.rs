@@ -0,0 +1,169 @@
+//! Request guards, predicates a request has to pass for a route to match.
+//!
+//! Routes sharing a method and path are tried in the order they were
+//! registered, the first one whose guards all pass serves the request:
+//!
+//! ```no_run
+//! use tinyweb::guard::{content_type, header};
+//! # use tinyweb::{RequestCtx, Server};
+//! # async fn create_v2(_ctx: RequestCtx) -> &'static str { "" }
+//! # async fn create_json(_ctx: RequestCtx) -> &'static str { "" }
+//! # async fn create_form(_ctx: RequestCtx) -> &'static str { "" }
+//! # let mut srv = Server::new();
+//!
+//! srv.post("/users", create_v2).guard(header("X-Api-Version", "2"));
+//! srv.post("/users", create_json).guard(content_type("application/json"));
+//! srv.post("/users", create_form);
+//! ```
+
+use crate::HyperRequest;
+
+pub trait Guard: Send + Sync + 'static {
+    fn check(&self, req: &HyperRequest) -> bool;
+}
+
+impl<F> Guard for F
+where
+    F: Fn(&HyperRequest) -> bool + Send + Sync + 'static,
+{
+    fn check(&self, req: &HyperRequest) -> bool {
+        self(req)
+    }
+}
+
+/// The media type of a header value, without parameters.
+pub(crate) fn essence(value: &str) -> &str {
+    value.split(';').next().unwrap_or(value).trim()
+}
+
+/// Matches requests with a `name` header equal to `value`.
+pub fn header(name: impl ToString, value: impl ToString) -> impl Guard {
+    let name = name.to_string();
+    let value = value.to_string();
+    move |req: &HyperRequest| {
+        req.headers()
+            .get_all(name.as_str())
+            .iter()
+            .any(|v| v.to_str().ok().map(str::trim) == Some(value.as_str()))
+    }
+}
+
+/// Matches requests whose `Content-Type` is `mime`, ignoring parameters such
+/// as `charset`.
+pub fn content_type(mime: impl ToString) -> impl Guard {
+    let mime = mime.to_string();
+    move |req: &HyperRequest| {
+        req.headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| essence(v).eq_ignore_ascii_case(&mime))
+    }
+}
+
+/// Matches requests accepting `mime`, either explicitly or through a range
+/// such as `text/*` or `*/*`. The most specific matching range decides, one
+/// with `q=0` excludes `mime`. Requests without an `Accept` header accept
+/// anything.
+pub fn accept(mime: impl ToString) -> impl Guard {
+    let mime = mime.to_string().to_ascii_lowercase();
+    move |req: &HyperRequest| {
+        let mut values = req
+            .headers()
+            .get_all(hyper::header::ACCEPT)
+            .iter()
+            .peekable();
+        if values.peek().is_none() {
+            return true;
+        }
+
+        values
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|range| {
+                let specificity = match essence(range).to_ascii_lowercase() {
+                    essence if essence == mime => 2,
+                    essence => match essence.strip_suffix("/*") {
+                        Some("*") => 0,
+                        Some(kind) if mime.split('/').next() == Some(kind) => 1,
+                        _ => return None,
+                    },
+                };
+                Some((specificity, quality(range)))
+            })
+            .max_by_key(|&(specificity, _)| specificity)
+            .is_some_and(|(_, q)| q > 0.0)
+    }
+}
+
+/// The `q` parameter of an `Accept` range, 1 if missing or invalid.
+fn quality(range: &str) -> f32 {
+    range
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+        .and_then(|(_, value)| value.trim().parse().ok())
+        .unwrap_or(1.0)
+}
+
+/// Matches requests whose query string has a `name` parameter equal to
+/// `value`.
+pub fn query(name: impl ToString, value: impl ToString) -> impl Guard {
+    let name = name.to_string();
+    let value = value.to_string();
+    move |req: &HyperRequest| {
+        let query = req.uri().query().unwrap_or("");
+        serde_urlencoded::from_str::<Vec<(String, String)>>(query)
+            .map(|pairs| pairs.iter().any(|(n, v)| *n == name && *v == value))
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(headers: &[(&str, &str)]) -> HyperRequest {
+        let mut req = hyper::Request::get("/?format=csv&page=2");
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        req.body(hyper::Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn accept_ranges() {
+        let guard = accept("application/json");
+
+        assert!(guard.check(&request(&[])));
+        assert!(guard.check(&request(&[("accept", "text/html, application/json;q=0.9")])));
+        assert!(guard.check(&request(&[("accept", "application/*")])));
+        assert!(guard.check(&request(&[("accept", "*/*")])));
+        assert!(!guard.check(&request(&[("accept", "text/*")])));
+        assert!(!guard.check(&request(&[("accept", "text/html")])));
+
+        assert!(!guard.check(&request(&[("accept", "application/json;q=0")])));
+        assert!(!guard.check(&request(&[("accept", "application/json; Q=0.0, */*")])));
+        assert!(!guard.check(&request(&[("accept", "application/*;q=0, text/*")])));
+        assert!(guard.check(&request(&[("accept", "*/*;q=0, application/json;q=0.1")])));
+        assert!(guard.check(&request(&[(
+            "accept",
+            "application/*;q=0, application/json"
+        )])));
+    }
+
+    #[test]
+    fn headers_content_types_and_queries() {
+        let req = request(&[
+            ("x-api-version", " 2 "),
+            ("content-type", "Application/JSON; charset=utf-8"),
+        ]);
+
+        assert!(header("X-Api-Version", "2").check(&req));
+        assert!(!header("X-Api-Version", "1").check(&req));
+        assert!(content_type("application/json").check(&req));
+        assert!(!content_type("text/plain").check(&req));
+        assert!(query("format", "csv").check(&req));
+        assert!(!query("format", "json").check(&req));
+    }
+}
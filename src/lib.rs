@@ -1,38 +1,848 @@
+use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::convert::Infallible;
+use std::fmt;
 use std::future::Future;
 use std::net::SocketAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Instant;
 
-use route_recognizer::{Params, Router as MethodRouter};
-
+use hyper::body::HttpBody;
 use hyper::service::{make_service_fn, service_fn};
+use hyper::Method;
+use serde::de::DeserializeOwned;
+
+mod extract;
+pub mod guard;
+mod multipart;
+mod problem;
+mod response;
+mod router;
+
+pub use extract::{Extension, Form, FromRequest, IntoHandler, Json, Path, Query, State};
+pub use guard::Guard;
+pub use multipart::{Field, Multipart, MultipartError};
+pub use problem::Problem;
+pub use response::{Html, IntoResponse};
+pub use router::Params;
+use router::{Captures, Constraint, HostPattern, MethodMap, Segment, Source};
 
 macro_rules! register_method {
     ($method_name: ident, $method_def: expr) => {
-        pub fn $method_name(&mut self, path: impl AsRef<str>, handler: impl HTTPHandler) {
+        pub fn $method_name<Args>(
+            &mut self,
+            path: impl AsRef<str>,
+            handler: impl IntoHandler<Args>,
+        ) -> &mut Route {
             self.register($method_def, path, handler)
         }
     };
 }
 
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// An error with the status of the response it turns into. Handlers
+/// returning `Result<_, Error>` can use `?` on the errors of this crate, I/O
+/// and JSON errors, or attach any other error as the source:
+///
+/// ```no_run
+/// # use hyper::StatusCode;
+/// # use tinyweb::{Error, Json, Path, State};
+/// # #[derive(Clone)]
+/// # struct Db;
+/// # #[derive(serde::Serialize)]
+/// # struct User {}
+/// # impl Db {
+/// #     async fn user(&self, _id: u64) -> std::io::Result<Option<User>> { Ok(None) }
+/// # }
+/// async fn show(Path(id): Path<u64>, State(db): State<Db>) -> Result<Json<User>, Error> {
+///     let user = db.user(id).await.map_err(|e| Error::new("loading user").with_source(e))?;
+///     let user = user.ok_or_else(|| {
+///         Error::new("no such user").with_status(StatusCode::NOT_FOUND)
+///     })?;
+///     Ok(Json(user))
+/// }
+/// # tinyweb::Server::new().get("/users/:id", show);
+/// ```
+///
+/// Errors are turned into responses by [`Server::error_handler`].
 #[derive(Debug)]
-pub struct Error(String);
+pub struct Error {
+    status: hyper::StatusCode,
+    message: String,
+    source: Option<BoxError>,
+    /// The message is the one of the source, which is not part of the chain.
+    transparent: bool,
+}
 
 impl Error {
-    fn new(msg: impl ToString) -> Self {
-        Error(msg.to_string())
+    /// A `500 Internal Server Error`.
+    pub fn new(msg: impl ToString) -> Self {
+        Error {
+            status: hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            message: msg.to_string(),
+            source: None,
+            transparent: false,
+        }
+    }
+
+    /// Wrap `err`, keeping its message.
+    fn wrap(
+        status: hyper::StatusCode,
+        err: impl std::error::Error + Send + Sync + 'static,
+    ) -> Self {
+        Error {
+            status,
+            message: err.to_string(),
+            source: Some(Box::new(err)),
+            transparent: true,
+        }
+    }
+
+    pub fn with_status(mut self, status: hyper::StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Set the error which caused this one.
+    pub fn with_source(mut self, source: impl Into<BoxError>) -> Self {
+        self.source = Some(source.into());
+        self.transparent = false;
+        self
+    }
+
+    pub fn status(&self) -> hyper::StatusCode {
+        self.status
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The error this one was converted from or caused by if it is an `E`,
+    /// e.g. the [`JsonError`] of a rejected request.
+    pub fn downcast_ref<E: std::error::Error + 'static>(&self) -> Option<&E> {
+        self.source.as_ref()?.downcast_ref()
+    }
+}
+
+/// The message, followed by the ones of the sources with `{:#}`.
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+        if f.alternate() {
+            let mut source = std::error::Error::source(self);
+            while let Some(err) = source {
+                write!(f, ": {}", err)?;
+                source = err.source();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        let source = self.source.as_deref()?;
+        if self.transparent {
+            source.source()
+        } else {
+            Some(source)
+        }
+    }
+}
+
+/// The response has the status and message of the error, until
+/// [`Server::error_handler`] replaces it. Server errors only get the reason
+/// phrase of their status, their message is for the log.
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        let body = if self.status.is_server_error() {
+            self.status.canonical_reason().unwrap_or("").to_string()
+        } else {
+            self.message.clone()
+        };
+        let mut resp = (self.status, body).into_response();
+        resp.extensions_mut().insert(self);
+        resp
+    }
+}
+
+macro_rules! error_from {
+    ($($ty:ty => |$e:ident| $status:expr,)*) => {
+        $(
+            impl From<$ty> for Error {
+                fn from($e: $ty) -> Self {
+                    Error::wrap($status, $e)
+                }
+            }
+        )*
+    };
+}
+
+error_from! {
+    ParamError => |e| e.status(),
+    QueryError => |e| e.status(),
+    JsonError => |e| e.status(),
+    FormError => |e| e.status(),
+    MultipartError => |e| e.status(),
+    UrlError => |_e| hyper::StatusCode::INTERNAL_SERVER_ERROR,
+    hyper::Error => |_e| hyper::StatusCode::INTERNAL_SERVER_ERROR,
+    std::io::Error => |_e| hyper::StatusCode::INTERNAL_SERVER_ERROR,
+    serde_json::Error => |_e| hyper::StatusCode::INTERNAL_SERVER_ERROR,
+}
+
+/// Turns the errors returned by handlers and extractors into responses, see
+/// [`Server::error_handler`].
+pub trait ErrorHandler: Send + Sync + 'static {
+    fn handle(&self, err: Error) -> Response;
+}
+
+impl<F, R> ErrorHandler for F
+where
+    F: Fn(Error) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    fn handle(&self, err: Error) -> Response {
+        self(err).into_response()
+    }
+}
+
+/// Error returned when building the url of a named route fails.
+#[derive(Debug)]
+pub enum UrlError {
+    /// No route was registered with this name.
+    UnknownRoute(String),
+    /// The route pattern needs a parameter which was not given.
+    MissingParam { route: String, param: String },
+}
+
+impl fmt::Display for UrlError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UrlError::UnknownRoute(route) => write!(f, "no route named {:?}", route),
+            UrlError::MissingParam { route, param } => {
+                write!(f, "route {:?} needs parameter {:?}", route, param)
+            }
+        }
+    }
+}
+
+impl std::error::Error for UrlError {}
+
+/// Error returned by [`RequestCtx::param`].
+#[derive(Debug)]
+pub enum ParamError {
+    /// The matched route has no such parameter.
+    Missing(String),
+    /// The parameter could not be parsed into the requested type.
+    Invalid {
+        name: String,
+        value: String,
+        reason: String,
+    },
+}
+
+impl ParamError {
+    /// `400 Bad Request` for unparsable values, `500 Internal Server Error` if
+    /// the handler asked for a parameter its route does not have.
+    pub fn status(&self) -> hyper::StatusCode {
+        match self {
+            ParamError::Missing(_) => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+            ParamError::Invalid { .. } => hyper::StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl IntoResponse for ParamError {
+    fn into_response(self) -> Response {
+        Error::from(self).into_response()
+    }
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::Missing(name) => write!(f, "missing path parameter {:?}", name),
+            ParamError::Invalid {
+                name,
+                value,
+                reason,
+            } => write!(
+                f,
+                "invalid path parameter {:?} = {:?}: {}",
+                name, value, reason
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+/// Error returned by [`RequestCtx::query`].
+#[derive(Debug)]
+pub struct QueryError {
+    /// The offending field, `None` if the error is about the query as a whole
+    /// such as a missing field, which `reason` then names.
+    pub field: Option<String>,
+    pub reason: String,
+}
+
+impl QueryError {
+    fn new(err: serde_path_to_error::Error<serde_urlencoded::de::Error>) -> Self {
+        let path = err.path();
+        let field = path.iter().next().map(|_| path.to_string());
+
+        QueryError {
+            field,
+            reason: err.into_inner().to_string(),
+        }
+    }
+
+    /// Always `400 Bad Request`.
+    pub fn status(&self) -> hyper::StatusCode {
+        hyper::StatusCode::BAD_REQUEST
+    }
+}
+
+impl IntoResponse for QueryError {
+    fn into_response(self) -> Response {
+        Error::from(self).into_response()
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "invalid query parameter {:?}: {}", field, self.reason),
+            None => write!(f, "invalid query string: {}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Error returned by [`RequestCtx::json`].
+#[derive(Debug)]
+pub enum JsonError {
+    /// The `Content-Type` is not JSON, holds the one sent if any.
+    ContentType(Option<String>),
+    /// The body is larger than the limit, see [`Server::body_limit`].
+    TooLarge { limit: usize },
+    /// Reading the body failed.
+    Read(hyper::Error),
+    /// The body is not valid JSON.
+    Malformed(serde_json::Error),
+    /// The body is valid JSON but does not fit the expected type, `field` is
+    /// the path of the offending value if known.
+    Mismatch {
+        field: Option<String>,
+        error: serde_json::Error,
+    },
+}
+
+impl JsonError {
+    fn new(err: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let path = err.path();
+        let field = path.iter().next().map(|_| path.to_string());
+        let error = err.into_inner();
+
+        match error.classify() {
+            serde_json::error::Category::Data => JsonError::Mismatch { field, error },
+            _ => JsonError::Malformed(error),
+        }
+    }
+
+    /// `415 Unsupported Media Type`, `413 Payload Too Large`, `422
+    /// Unprocessable Entity` for a mismatch and `400 Bad Request` otherwise.
+    pub fn status(&self) -> hyper::StatusCode {
+        match self {
+            JsonError::ContentType(_) => hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonError::TooLarge { .. } => hyper::StatusCode::PAYLOAD_TOO_LARGE,
+            JsonError::Read(_) | JsonError::Malformed(_) => hyper::StatusCode::BAD_REQUEST,
+            JsonError::Mismatch { .. } => hyper::StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        Error::from(self).into_response()
+    }
+}
+
+impl From<BodyError> for JsonError {
+    fn from(err: BodyError) -> Self {
+        match err {
+            BodyError::TooLarge(limit) => JsonError::TooLarge { limit },
+            BodyError::Read(e) => JsonError::Read(e),
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::ContentType(Some(content_type)) => {
+                write!(f, "expected a JSON body, got {:?}", content_type)
+            }
+            JsonError::ContentType(None) => {
+                f.write_str("expected a JSON body, got no content type")
+            }
+            JsonError::TooLarge { limit } => write!(f, "body larger than {} bytes", limit),
+            JsonError::Read(e) => write!(f, "failed to read body: {}", e),
+            JsonError::Malformed(e) => write!(f, "malformed JSON: {}", e),
+            JsonError::Mismatch {
+                field: Some(field),
+                error,
+            } => write!(f, "invalid JSON field {:?}: {}", field, error),
+            JsonError::Mismatch { field: None, error } => write!(f, "invalid JSON: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for JsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JsonError::Read(e) => Some(e),
+            JsonError::Malformed(e) | JsonError::Mismatch { error: e, .. } => Some(e),
+            _ => None,
+        }
+    }
+}
+
+fn parse_urlencoded<T: DeserializeOwned>(input: &[u8]) -> Result<T, QueryError> {
+    let de = serde_urlencoded::Deserializer::new(url::form_urlencoded::parse(input));
+    serde_path_to_error::deserialize(de).map_err(QueryError::new)
+}
+
+/// Error returned by [`RequestCtx::form`].
+#[derive(Debug)]
+pub enum FormError {
+    /// The `Content-Type` is not `application/x-www-form-urlencoded`, holds
+    /// the one sent if any.
+    ContentType(Option<String>),
+    /// The body is larger than the limit, see [`Server::body_limit`].
+    TooLarge { limit: usize },
+    /// Reading the body failed.
+    Read(hyper::Error),
+    /// The form does not fit the expected type, as for a query string.
+    Invalid(QueryError),
+}
+
+impl FormError {
+    /// `415 Unsupported Media Type`, `413 Payload Too Large` and `400 Bad
+    /// Request` otherwise.
+    pub fn status(&self) -> hyper::StatusCode {
+        match self {
+            FormError::ContentType(_) => hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::TooLarge { .. } => hyper::StatusCode::PAYLOAD_TOO_LARGE,
+            FormError::Read(_) => hyper::StatusCode::BAD_REQUEST,
+            FormError::Invalid(e) => e.status(),
+        }
+    }
+}
+
+impl IntoResponse for FormError {
+    fn into_response(self) -> Response {
+        Error::from(self).into_response()
+    }
+}
+
+impl From<BodyError> for FormError {
+    fn from(err: BodyError) -> Self {
+        match err {
+            BodyError::TooLarge(limit) => FormError::TooLarge { limit },
+            BodyError::Read(e) => FormError::Read(e),
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::ContentType(Some(content_type)) => {
+                write!(f, "expected a form body, got {:?}", content_type)
+            }
+            FormError::ContentType(None) => {
+                f.write_str("expected a form body, got no content type")
+            }
+            FormError::TooLarge { limit } => write!(f, "body larger than {} bytes", limit),
+            FormError::Read(e) => write!(f, "failed to read body: {}", e),
+            FormError::Invalid(QueryError {
+                field: Some(field),
+                reason,
+            }) => write!(f, "invalid form field {:?}: {}", field, reason),
+            FormError::Invalid(QueryError {
+                field: None,
+                reason,
+            }) => write!(f, "invalid form: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FormError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FormError::Read(e) => Some(e),
+            FormError::Invalid(e) => Some(e),
+            _ => None,
+        }
     }
 }
 
+/// Why reading a request body failed.
+enum BodyError {
+    TooLarge(usize),
+    Read(hyper::Error),
+}
+
+/// Default for [`Server::body_limit`].
+const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
 pub type HyperRequest = hyper::Request<hyper::Body>;
 pub type Response = hyper::Response<hyper::Body>;
 
 pub struct RequestCtx {
     pub request: HyperRequest,
     pub params: Params,
+    /// Labels captured by the host pattern of the route.
+    pub host_params: Params,
     pub remote_addr: SocketAddr,
+    /// Values passed down the middleware chain for this request, such as the
+    /// authenticated user.
+    ///
+    /// ```no_run
+    /// # use tinyweb::RequestCtx;
+    /// # struct CurrentUser { id: u64 }
+    /// # fn middleware(mut ctx: RequestCtx, id: u64) {
+    /// // in a middleware
+    /// ctx.extensions.insert(CurrentUser { id });
+    /// # }
+    /// # fn handler(ctx: RequestCtx) {
+    /// // in the handler
+    /// let user = ctx.extensions.get::<CurrentUser>();
+    /// # }
+    /// ```
+    pub extensions: Extensions,
+    original_uri: Option<hyper::Uri>,
+    urls: Arc<Urls>,
+    routes: Arc<[RouteInfo]>,
+    state: Arc<Extensions>,
+    body_limit: usize,
+    error_handler: Option<Arc<dyn ErrorHandler>>,
+}
+
+impl RequestCtx {
+    /// Parse the path parameter `name`.
+    ///
+    /// ```no_run
+    /// # use tinyweb::{ParamError, RequestCtx};
+    /// # fn handler(ctx: RequestCtx) -> Result<(), ParamError> {
+    /// let id: u64 = ctx.param("id")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn param<T>(&self, name: &str) -> Result<T, ParamError>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        let value = self
+            .params
+            .find(name)
+            .ok_or_else(|| ParamError::Missing(name.to_string()))?;
+
+        value.parse().map_err(|e: T::Err| ParamError::Invalid {
+            name: name.to_string(),
+            value: value.to_string(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// Deserialize the query string, a missing one is treated as empty.
+    ///
+    /// ```no_run
+    /// # use tinyweb::{QueryError, RequestCtx};
+    /// #[derive(serde::Deserialize)]
+    /// struct Page {
+    ///     page: u32,
+    ///     per_page: Option<u32>,
+    /// }
+    ///
+    /// # fn handler(ctx: RequestCtx) -> Result<(), QueryError> {
+    /// let page: Page = ctx.query()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, QueryError> {
+        let query = self.request.uri().query().unwrap_or("");
+        parse_urlencoded(query.as_bytes())
+    }
+
+    /// Deserialize a JSON body, sent with an `application/json` or `+json`
+    /// content type. The body is consumed, later calls see an empty one.
+    ///
+    /// ```no_run
+    /// # use tinyweb::{JsonError, RequestCtx};
+    /// # #[derive(serde::Deserialize)]
+    /// # struct NewUser {}
+    /// # async fn handler(mut ctx: RequestCtx) -> Result<(), JsonError> {
+    /// let user: NewUser = ctx.json().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn json<T: DeserializeOwned>(&mut self) -> Result<T, JsonError> {
+        let content_type = self.content_type();
+        let is_json = content_type
+            .as_deref()
+            .map(guard::essence)
+            .is_some_and(|essence| {
+                let essence = essence.to_ascii_lowercase();
+                essence == "application/json" || essence.ends_with("+json")
+            });
+        if !is_json {
+            return Err(JsonError::ContentType(content_type));
+        }
+
+        let body = self.read_body().await?;
+
+        let mut de = serde_json::Deserializer::from_slice(&body);
+        let value = serde_path_to_error::deserialize(&mut de).map_err(JsonError::new)?;
+        de.end().map_err(JsonError::Malformed)?;
+        Ok(value)
+    }
+
+    /// Deserialize an `application/x-www-form-urlencoded` body, as posted by
+    /// HTML forms. The body is consumed, later calls see an empty one.
+    ///
+    /// ```no_run
+    /// # use tinyweb::{FormError, RequestCtx};
+    /// # #[derive(serde::Deserialize)]
+    /// # struct Login {}
+    /// # async fn handler(mut ctx: RequestCtx) -> Result<(), FormError> {
+    /// let login: Login = ctx.form().await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn form<T: DeserializeOwned>(&mut self) -> Result<T, FormError> {
+        let content_type = self.content_type();
+        let is_form = content_type
+            .as_deref()
+            .map(guard::essence)
+            .is_some_and(|essence| {
+                essence.eq_ignore_ascii_case("application/x-www-form-urlencoded")
+            });
+        if !is_form {
+            return Err(FormError::ContentType(content_type));
+        }
+
+        let body = self.read_body().await?;
+        parse_urlencoded(&body).map_err(FormError::Invalid)
+    }
+
+    /// Read a `multipart/form-data` body part by part, as sent by forms
+    /// uploading files. The body is read while the parts are rather than
+    /// buffered, so the body limit does not apply, see
+    /// [`Multipart::total_limit`] and [`Multipart::part_limit`] instead.
+    pub fn multipart(&mut self) -> Result<Multipart, MultipartError> {
+        let content_type = self.content_type();
+        let boundary = match content_type.as_deref().and_then(Multipart::boundary) {
+            Some(boundary) => boundary,
+            None => return Err(MultipartError::ContentType(content_type)),
+        };
+
+        let body = std::mem::take(self.request.body_mut());
+        Ok(Multipart::new(body, &boundary))
+    }
+
+    /// Change the body size limit for this request, e.g. from a middleware
+    /// of a route taking large documents. See [`Server::body_limit`].
+    pub fn set_body_limit(&mut self, limit: usize) {
+        self.body_limit = limit;
+    }
+
+    fn content_type(&self) -> Option<String> {
+        self.request
+            .headers()
+            .get(hyper::header::CONTENT_TYPE)
+            .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+    }
+
+    /// Take the whole body, failing once it is larger than the body limit.
+    async fn read_body(&mut self) -> Result<Vec<u8>, BodyError> {
+        let limit = self.body_limit;
+        let too_large = self
+            .request
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.parse::<u64>().ok())
+            .is_some_and(|len| len > limit as u64);
+        if too_large {
+            return Err(BodyError::TooLarge(limit));
+        }
+
+        let mut body = std::mem::take(self.request.body_mut());
+        let mut buf = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(BodyError::Read)?;
+            if buf.len() + chunk.len() > limit {
+                return Err(BodyError::TooLarge(limit));
+            }
+            buf.extend_from_slice(&chunk);
+        }
+
+        Ok(buf)
+    }
+
+    /// The application state of type `T`, see [`Server::state`].
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get()
+    }
+
+    /// The url generator for the named routes of the server.
+    pub fn urls(&self) -> &Urls {
+        &self.urls
+    }
+
+    /// The routes of the server, see [`Server::routes`].
+    pub fn routes(&self) -> &[RouteInfo] {
+        &self.routes
+    }
+
+    /// Build the path of a named route, see [`Urls::url_for`].
+    pub fn url_for<K, V>(
+        &self,
+        name: &str,
+        params: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<String, UrlError>
+    where
+        K: AsRef<str>,
+        V: ToString,
+    {
+        self.urls.url_for(name, params)
+    }
+
+    /// The request uri as received, before any mount prefix was stripped
+    /// from `request.uri()`.
+    pub fn original_uri(&self) -> &hyper::Uri {
+        self.original_uri
+            .as_ref()
+            .unwrap_or_else(|| self.request.uri())
+    }
+}
+
+/// Values keyed by their type, at most one per type.
+#[derive(Debug, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Insert `value`, returning the previous value of this type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|old| old.downcast().ok().map(|old| *old))
+    }
+
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.map.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    pub fn get_mut<T: Send + Sync + 'static>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>())?.downcast_mut()
+    }
+
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok().map(|value| *value))
+    }
+
+    /// Add the values of `other` whose type is missing here.
+    fn merge(&mut self, other: Extensions) {
+        for (id, value) in other.map {
+            self.map.entry(id).or_insert(value);
+        }
+    }
+}
+
+/// Reverse routing, maps route names to their path patterns.
+#[derive(Debug, Default)]
+pub struct Urls {
+    patterns: HashMap<String, String>,
+}
+
+impl Urls {
+    fn new(routes: &[Route]) -> Self {
+        let patterns = routes
+            .iter()
+            .filter_map(|route| Some((route.name.clone()?, route.path.clone())))
+            .collect();
+
+        Urls { patterns }
+    }
+
+    /// Build the path of the route registered as `name`, filling its
+    /// `:param`, `{param}` and `*param` segments from `params`.
+    pub fn url_for<K, V>(
+        &self,
+        name: &str,
+        params: impl IntoIterator<Item = (K, V)>,
+    ) -> Result<String, UrlError>
+    where
+        K: AsRef<str>,
+        V: ToString,
+    {
+        let pattern = self
+            .patterns
+            .get(name)
+            .ok_or_else(|| UrlError::UnknownRoute(name.to_string()))?;
+
+        let params: HashMap<String, String> = params
+            .into_iter()
+            .map(|(k, v)| (k.as_ref().to_string(), v.to_string()))
+            .collect();
+
+        let mut url = String::with_capacity(pattern.len());
+        for (i, segment) in pattern.split('/').enumerate() {
+            if i > 0 {
+                url.push('/');
+            }
+
+            let (param, keep_slash) = match Segment::parse(segment) {
+                Segment::Static(segment) => {
+                    url.push_str(segment);
+                    continue;
+                }
+                Segment::Param(param, _) => (param, false),
+                Segment::Wildcard(param) => (param, true),
+            };
+
+            let value = params.get(param).ok_or_else(|| UrlError::MissingParam {
+                route: name.to_string(),
+                param: param.to_string(),
+            })?;
+            percent_encode(value, keep_slash, &mut url);
+        }
+
+        Ok(url)
+    }
+}
+
+/// Percent-encode everything but unreserved characters (and `/` if asked).
+fn percent_encode(value: &str, keep_slash: bool, out: &mut String) {
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                out.push(b as char)
+            }
+            b'/' if keep_slash => out.push('/'),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
 }
 
 pub struct ResponseBuiler;
@@ -62,6 +872,25 @@ impl ResponseBuiler {
             .unwrap()
     }
 
+    /// `value` serialized as JSON, a `500 Internal Server Error` if that
+    /// fails.
+    pub fn with_json(value: &impl serde::Serialize) -> Response {
+        match serde_json::to_vec(value) {
+            Ok(json) => hyper::Response::builder()
+                .header(
+                    "Content-type".parse::<hyper::header::HeaderName>().unwrap(),
+                    "application/json"
+                        .parse::<hyper::header::HeaderValue>()
+                        .unwrap(),
+                )
+                .body(hyper::Body::from(json))
+                .unwrap(),
+            Err(e) => Error::new("failed to serialize response")
+                .with_source(e)
+                .into_response(),
+        }
+    }
+
     pub fn with_status(status: hyper::StatusCode) -> Response {
         hyper::Response::builder()
             .status(status)
@@ -81,50 +910,305 @@ type BoxHTTPHandler = Box<dyn HTTPHandler>;
 impl<F: Send + Sync + 'static, Fut> HTTPHandler for F
 where
     F: Fn(RequestCtx) -> Fut,
-    Fut: Future<Output = Response> + Send + 'static,
+    Fut: Future + Send + 'static,
+    Fut::Output: IntoResponse,
 {
     async fn handle(&self, ctx: RequestCtx) -> Response {
-        self(ctx).await
+        self(ctx).await.into_response()
     }
 }
 
-type Router = HashMap<String, MethodRouter<BoxHTTPHandler>>;
+/// Routes by path and method, routes differing only in their parameter
+/// constraints or host share a slot and are tried in turn.
+type Router = router::Router<MethodMap<Vec<Route>>>;
 
-#[async_trait::async_trait]
-pub trait Middleware: Send + Sync + 'static {
-    async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response;
+/// The host a request was sent to, without port.
+fn request_host(req: &HyperRequest) -> Option<&str> {
+    if let Some(host) = req.uri().host() {
+        return Some(host);
+    }
+
+    let host = req.headers().get(hyper::header::HOST)?.to_str().ok()?;
+    if host.starts_with('[') {
+        // IPv6 literal
+        return host.find(']').map(|i| &host[..=i]);
+    }
+    Some(host.split(':').next().unwrap_or(host))
 }
 
-#[allow(missing_debug_implementations)]
-pub struct Next<'a> {
-    pub(crate) endpoint: &'a dyn HTTPHandler,
-    pub(crate) next_middleware: &'a [Arc<dyn Middleware>],
+/// A registered handler together with the middleware that only applies to it,
+/// returned by the registration methods to configure the route further.
+pub struct Route {
+    method: String,
+    path: String,
+    name: Option<String>,
+    host: Option<String>,
+    handler: BoxHTTPHandler,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    /// How many of the middlewares were added by the framework, such as the
+    /// prefix stripping of mounted apps, and are left out of listings.
+    internal_middlewares: usize,
+    guards: Vec<Box<dyn Guard>>,
+    param_names: Arc<[String]>,
+    constraints: Vec<(usize, Constraint)>,
+    host_pattern: Option<HostPattern>,
 }
 
-impl<'a> Next<'a> {
-    /// Asynchronously execute the remaining middleware chain.
-    pub async fn run(mut self, ctx: RequestCtx) -> Response {
-        if let Some((current, next)) = self.next_middleware.split_first() {
-            self.next_middleware = next;
-            current.handle(ctx, self).await
-        } else {
-            (self.endpoint).handle(ctx).await
+impl Route {
+    fn new(method: impl ToString, path: impl AsRef<str>, handler: BoxHTTPHandler) -> Self {
+        let path = path.as_ref();
+        Route {
+            method: method.to_string().to_uppercase(),
+            // the router implies the leading `/`, make it explicit so that
+            // comparing and joining paths agrees with it
+            path: match path.strip_prefix('/') {
+                Some(_) => path.to_string(),
+                None => format!("/{}", path),
+            },
+            name: None,
+            host: None,
+            handler,
+            middlewares: Vec::new(),
+            internal_middlewares: 0,
+            guards: Vec::new(),
+            param_names: Arc::new([]),
+            constraints: Vec::new(),
+            host_pattern: None,
         }
     }
-}
 
-pub struct AccessLog;
+    /// Compile the parameter constraints and the host pattern of the route.
+    fn compile(&mut self) -> Result<(), Error> {
+        let mut names = Vec::new();
+        for segment in self.path.split('/') {
+            match Segment::parse(segment) {
+                Segment::Static(_) => {}
+                Segment::Wildcard(name) => names.push(name.to_string()),
+                Segment::Param(name, constraint) => {
+                    if let Some(spec) = constraint {
+                        let constraint = Constraint::new(spec).map_err(|e| {
+                            Error::new(format!(
+                                "invalid constraint for {:?} in route {:?}: {}",
+                                name, self.path, e
+                            ))
+                        })?;
+                        self.constraints.push((names.len(), constraint));
+                    }
+                    names.push(name.to_string());
+                }
+            }
+        }
+        self.param_names = names.into();
 
-#[async_trait::async_trait]
-impl Middleware for AccessLog {
-    async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response {
-        let start = Instant::now();
-        let method = ctx.request.method().to_string();
-        let path = ctx.request.uri().path().to_string();
-        let remote_addr = ctx.remote_addr;
-        let res = next.run(ctx).await;
-        println!(
-            "{} {:?} {} {} {}ms",
+        if let Some(host) = &self.host {
+            let pattern = HostPattern::parse(host).map_err(|e| {
+                Error::new(format!(
+                    "invalid host {:?} for route {:?}: {}",
+                    host, self.path, e
+                ))
+            })?;
+            self.host_pattern = Some(pattern);
+        }
+
+        Ok(())
+    }
+
+    fn info(&self, global_middlewares: usize) -> RouteInfo {
+        RouteInfo {
+            method: self.method.clone(),
+            path: self.path.clone(),
+            name: self.name.clone(),
+            host: self.host.clone(),
+            middlewares: global_middlewares + self.middlewares.len() - self.internal_middlewares,
+        }
+    }
+
+    /// Check the parameter constraints and the host of the route, returning
+    /// the parameters captured from the host.
+    fn matches(&self, path: &str, captures: &Captures, host: Option<&str>) -> Option<Captures> {
+        let constrained = self.constraints.iter().all(|(i, constraint)| {
+            captures
+                .get(path, *i)
+                .is_some_and(|value| constraint.matches(value))
+        });
+        if !constrained {
+            return None;
+        }
+
+        match &self.host_pattern {
+            Some(pattern) => pattern.matches(host?),
+            None => Some(Captures::default()),
+        }
+    }
+
+    /// Name the route, so its url can be built with [`RequestCtx::url_for`].
+    pub fn name(&mut self, name: impl ToString) -> &mut Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Only match requests for hosts matching `pattern`, such as
+    /// `api.example.com` or `{tenant}.example.com`. Captured labels are
+    /// available in [`RequestCtx::host_params`].
+    pub fn host(&mut self, pattern: impl ToString) -> &mut Self {
+        self.host = Some(pattern.to_string());
+        self
+    }
+
+    /// Add a middleware which only runs for this route, after the global and
+    /// scope ones.
+    pub fn with(&mut self, middleware: impl Middleware) -> &mut Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Only match requests passing `guard`. Routes with the same method and
+    /// path are tried in registration order, see [`guard`].
+    pub fn guard(&mut self, guard: impl Guard) -> &mut Self {
+        self.guards.push(Box::new(guard));
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl HTTPHandler for Route {
+    async fn handle(&self, ctx: RequestCtx) -> Response {
+        let next = Next {
+            endpoint: &*self.handler,
+            next_middleware: &self.middlewares,
+        };
+
+        next.run(ctx).await
+    }
+}
+
+/// Description of a registered route, see [`Server::routes`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RouteInfo {
+    pub method: String,
+    pub path: String,
+    pub name: Option<String>,
+    pub host: Option<String>,
+    /// Number of middleware running for the route, including global ones.
+    pub middlewares: usize,
+}
+
+/// Built-in handler listing the routes of the server, e.g.
+///
+/// ```no_run
+/// # use tinyweb::{Middleware, Next, RequestCtx, Response, RouteListing, Server};
+/// # struct AdminOnly;
+/// # #[async_trait::async_trait]
+/// # impl Middleware for AdminOnly {
+/// #     async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response {
+/// #         next.run(ctx).await
+/// #     }
+/// # }
+/// # let mut srv = Server::new();
+/// srv.get("/_routes", RouteListing::Json).with(AdminOnly);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteListing {
+    Json,
+    Text,
+}
+
+#[async_trait::async_trait]
+impl HTTPHandler for RouteListing {
+    async fn handle(&self, ctx: RequestCtx) -> Response {
+        match self {
+            RouteListing::Json => ResponseBuiler::with_json(&ctx.routes()),
+            RouteListing::Text => {
+                let mut text = String::new();
+                for route in ctx.routes() {
+                    text.push_str(&format!(
+                        "{:<8} {} {} {} middlewares={}\n",
+                        route.method,
+                        route.path,
+                        route.host.as_deref().unwrap_or("*"),
+                        route.name.as_deref().unwrap_or("-"),
+                        route.middlewares
+                    ));
+                }
+                ResponseBuiler::with_text(text)
+            }
+        }
+    }
+}
+
+/// Join a scope prefix and a route path with exactly one slash between them
+/// and a leading one.
+fn join_path(prefix: &str, path: &str) -> String {
+    let prefix = prefix.trim_start_matches('/').trim_end_matches('/');
+    let path = path.trim_start_matches('/');
+    match (prefix.is_empty(), path.is_empty()) {
+        (true, _) => format!("/{}", path),
+        // the root below a prefix is the prefix itself
+        (false, true) => format!("/{}", prefix),
+        (false, false) => format!("/{}/{}", prefix, path),
+    }
+}
+
+#[async_trait::async_trait]
+pub trait Middleware: Send + Sync + 'static {
+    async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response;
+}
+
+#[allow(missing_debug_implementations)]
+pub struct Next<'a> {
+    pub(crate) endpoint: &'a dyn HTTPHandler,
+    pub(crate) next_middleware: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    /// Asynchronously execute the remaining middleware chain.
+    pub async fn run(mut self, ctx: RequestCtx) -> Response {
+        if let Some((current, next)) = self.next_middleware.split_first() {
+            self.next_middleware = next;
+            current.handle(ctx, self).await
+        } else {
+            let error_handler = ctx.error_handler.clone();
+            let resp = (self.endpoint).handle(ctx).await;
+            handle_error(error_handler.as_deref(), resp)
+        }
+    }
+}
+
+/// Pass the [`Error`] a response was made from to the error handler, or log
+/// it if it is a server error and there is none.
+fn handle_error(error_handler: Option<&dyn ErrorHandler>, mut resp: Response) -> Response {
+    let err = match resp.extensions_mut().remove::<Error>() {
+        Some(err) => err,
+        None => return resp,
+    };
+
+    match error_handler {
+        Some(error_handler) => {
+            let mut resp = error_handler.handle(err);
+            resp.extensions_mut().remove::<Error>();
+            resp
+        }
+        None => {
+            if err.status().is_server_error() {
+                eprintln!("unhandled error: {:#}", err);
+            }
+            resp
+        }
+    }
+}
+
+pub struct AccessLog;
+
+#[async_trait::async_trait]
+impl Middleware for AccessLog {
+    async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response {
+        let start = Instant::now();
+        let method = ctx.request.method().to_string();
+        let path = ctx.request.uri().path().to_string();
+        let remote_addr = ctx.remote_addr;
+        let res = next.run(ctx).await;
+        println!(
+            "{} {:?} {} {} {}ms",
             method,
             path,
             res.status().as_str(),
@@ -135,30 +1219,166 @@ impl Middleware for AccessLog {
     }
 }
 
+/// Strips the mount point of a sub-application from the request path, so the
+/// mounted routes see a path relative to it.
+struct StripPrefix {
+    segments: usize,
+}
+
+impl StripPrefix {
+    fn new(prefix: &str) -> Self {
+        StripPrefix {
+            segments: prefix.split('/').filter(|s| !s.is_empty()).count(),
+        }
+    }
+
+    fn strip<'p>(&self, path: &'p str) -> &'p str {
+        let mut rest = path;
+        for _ in 0..self.segments {
+            let segment = rest.trim_start_matches('/');
+            rest = segment.find('/').map_or("", |i| &segment[i..]);
+        }
+        rest
+    }
+}
+
+#[async_trait::async_trait]
+impl Middleware for StripPrefix {
+    async fn handle<'a>(&'a self, mut ctx: RequestCtx, next: Next<'a>) -> Response {
+        let uri = ctx.request.uri().clone();
+
+        let path = match self.strip(uri.path()) {
+            "" => "/",
+            path => path,
+        };
+
+        if let Some(stripped) = replace_path(&uri, path) {
+            *ctx.request.uri_mut() = stripped;
+            ctx.original_uri.get_or_insert(uri);
+        }
+
+        next.run(ctx).await
+    }
+}
+
+/// A group of routes sharing a path prefix and middleware, see [`Server::scope`].
+pub struct Scope {
+    prefix: String,
+    host: Option<String>,
+    routes: Vec<Route>,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl Scope {
+    fn new(prefix: impl AsRef<str>) -> Self {
+        Scope {
+            prefix: prefix.as_ref().to_string(),
+            host: None,
+            routes: Vec::new(),
+            middlewares: Vec::new(),
+        }
+    }
+
+    pub fn register<Args>(
+        &mut self,
+        method: impl ToString,
+        path: impl AsRef<str>,
+        handler: impl IntoHandler<Args>,
+    ) -> &mut Route {
+        self.routes
+            .push(Route::new(method, path, handler.into_handler()));
+        self.routes.last_mut().unwrap()
+    }
+
+    register_method!(get, "GET");
+    register_method!(head, "HEAD");
+    register_method!(post, "POST");
+    register_method!(put, "PUT");
+    register_method!(delete, "DELETE");
+    register_method!(connect, "CONNECT");
+    register_method!(options, "OPTIONS");
+    register_method!(trace, "TRACE");
+    register_method!(patch, "PATCH");
+
+    /// Add a middleware which only runs for the routes of this scope,
+    /// after the global ones.
+    pub fn middleware(&mut self, middleware: impl Middleware) {
+        self.middlewares.push(Arc::new(middleware));
+    }
+
+    /// Only match requests for hosts matching `pattern`, see [`Route::host`].
+    /// Routes with a host of their own keep it.
+    pub fn host(&mut self, pattern: impl ToString) {
+        self.host = Some(pattern.to_string());
+    }
+
+    /// Nest another scope below this one.
+    pub fn scope(&mut self, prefix: impl AsRef<str>, f: impl FnOnce(&mut Scope)) {
+        let mut scope = Scope::new(prefix);
+        f(&mut scope);
+        self.routes.extend(scope.into_routes());
+    }
+
+    fn into_routes(self) -> Vec<Route> {
+        let Self {
+            prefix,
+            host,
+            routes,
+            middlewares,
+        } = self;
+
+        routes
+            .into_iter()
+            .map(|mut route| {
+                route.path = join_path(&prefix, &route.path);
+                if route.host.is_none() {
+                    route.host = host.clone();
+                }
+                route.middlewares = middlewares
+                    .iter()
+                    .cloned()
+                    .chain(route.middlewares)
+                    .collect();
+                route
+            })
+            .collect()
+    }
+}
+
 pub struct Server {
-    router: Router,
+    routes: Vec<Route>,
     middlewares: Vec<Arc<dyn Middleware>>,
+    fallback: BoxHTTPHandler,
+    normalize_path: bool,
+    trailing_slash: TrailingSlash,
+    body_limit: usize,
+    state: Extensions,
+    error_handler: Option<Arc<dyn ErrorHandler>>,
 }
 
 impl Server {
     pub fn new() -> Self {
         Server {
-            router: HashMap::new(),
+            routes: Vec::new(),
             middlewares: Vec::new(),
+            fallback: Box::new(Self::handle_not_found),
+            normalize_path: false,
+            trailing_slash: TrailingSlash::default(),
+            body_limit: DEFAULT_BODY_LIMIT,
+            state: Extensions::default(),
+            error_handler: None,
         }
     }
 
-    pub fn register(
+    pub fn register<Args>(
         &mut self,
         method: impl ToString,
         path: impl AsRef<str>,
-        handler: impl HTTPHandler,
-    ) {
-        let method = method.to_string().to_uppercase();
-        self.router
-            .entry(method)
-            .or_insert_with(MethodRouter::new)
-            .add(path.as_ref(), Box::new(handler));
+        handler: impl IntoHandler<Args>,
+    ) -> &mut Route {
+        self.routes
+            .push(Route::new(method, path, handler.into_handler()));
+        self.routes.last_mut().unwrap()
     }
 
     register_method!(get, "GET");
@@ -175,55 +1395,225 @@ impl Server {
         self.middlewares.push(Arc::new(middleware));
     }
 
-    pub async fn run(self, addr: SocketAddr) -> Result<(), Error> {
+    /// List the registered routes, in registration order.
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        self.routes
+            .iter()
+            .map(|route| route.info(self.middlewares.len()))
+            .collect()
+    }
+
+    /// Set the handler for requests no route matches, instead of the default
+    /// empty `404 Not Found`. The global middleware runs around it as well.
+    pub fn fallback<Args>(&mut self, handler: impl IntoHandler<Args>) {
+        self.fallback = handler.into_handler();
+    }
+
+    /// Group routes under a common path prefix, e.g.
+    ///
+    /// ```no_run
+    /// # use tinyweb::{Middleware, Next, RequestCtx, Response, Server};
+    /// # struct Auth;
+    /// # #[async_trait::async_trait]
+    /// # impl Middleware for Auth {
+    /// #     async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response {
+    /// #         next.run(ctx).await
+    /// #     }
+    /// # }
+    /// # async fn list_users(_ctx: RequestCtx) -> &'static str { "" }
+    /// # let mut srv = Server::new();
+    /// srv.scope("/api/v1", |g| {
+    ///     g.get("/users", list_users);
+    ///     g.middleware(Auth);
+    /// });
+    /// ```
+    ///
+    /// Middleware added to the scope only runs for its routes, and its `/`
+    /// route answers at the prefix itself.
+    pub fn scope(&mut self, prefix: impl AsRef<str>, f: impl FnOnce(&mut Scope)) {
+        let mut scope = Scope::new(prefix);
+        f(&mut scope);
+        self.routes.extend(scope.into_routes());
+    }
+
+    /// Group routes only matching requests for hosts matching `pattern`, e.g.
+    ///
+    /// ```no_run
+    /// # use tinyweb::{RequestCtx, Server};
+    /// # async fn tenant_index(_ctx: RequestCtx) -> &'static str { "" }
+    /// # let mut srv = Server::new();
+    /// srv.host("{tenant}.example.com", |g| {
+    ///     g.get("/", tenant_index);
+    /// });
+    /// ```
+    pub fn host(&mut self, pattern: impl ToString, f: impl FnOnce(&mut Scope)) {
+        let mut scope = Scope::new("");
+        scope.host(pattern);
+        f(&mut scope);
+        self.routes.extend(scope.into_routes());
+    }
+
+    /// Mount another `Server` below `prefix`. The mounted app keeps its own
+    /// middleware and sees request paths relative to `prefix`, the original
+    /// one stays available through [`RequestCtx::original_uri`].
+    ///
+    /// The `/` route of the mounted app answers at `prefix` itself, e.g.
+    /// `/billing` rather than `/billing/`.
+    ///
+    /// Only the routes, middleware and state of the mounted app are taken
+    /// over. Its state is shared with this server, for the types this server
+    /// has no state of. Everything else is the one of this server: unmatched
+    /// requests are handled by its fallback and errors by its error handler,
+    /// and its body limit, path normalization and trailing slash policy apply
+    /// to the mounted routes too.
+    pub fn mount(&mut self, prefix: impl AsRef<str>, app: Server) {
+        let prefix = prefix.as_ref();
+        let strip_prefix: Arc<dyn Middleware> = Arc::new(StripPrefix::new(prefix));
+
         let Self {
-            router,
+            routes,
             middlewares,
-        } = self;
+            state,
+            ..
+        } = app;
+
+        self.state.merge(state);
+
+        for mut route in routes {
+            route.path = join_path(prefix, &route.path);
+            route.middlewares = std::iter::once(strip_prefix.clone())
+                .chain(middlewares.iter().cloned())
+                .chain(route.middlewares)
+                .collect();
+            route.internal_middlewares += 1;
+            self.routes.push(route);
+        }
+    }
+
+    /// Normalize request paths before routing, collapsing `//` and resolving
+    /// `.` and `..` segments. Disabled by default.
+    pub fn normalize_path(&mut self, enable: bool) {
+        self.normalize_path = enable;
+    }
+
+    /// Set how paths differing from a route only by a trailing slash are
+    /// handled, [`TrailingSlash::Strict`] by default.
+    pub fn trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
+    }
+
+    /// Set how the errors returned by handlers and extractors are turned into
+    /// responses, e.g.
+    ///
+    /// ```no_run
+    /// # use tinyweb::{Error, Html, Server};
+    /// # let mut srv = Server::new();
+    /// srv.error_handler(|err: Error| {
+    ///     if err.status().is_server_error() {
+    ///         eprintln!("{:#}", err);
+    ///     }
+    ///     (err.status(), Html(format!("<h1>{}</h1>", err.status())))
+    /// });
+    /// ```
+    ///
+    /// By default the response has the status and message of the error, and
+    /// server errors are logged to stderr. Their message is not sent, only
+    /// the reason phrase of the status. The middleware sees the response
+    /// returned by the hook, errors returned by middleware are handled the
+    /// same way.
+    pub fn error_handler(&mut self, handler: impl ErrorHandler) {
+        self.error_handler = Some(Arc::new(handler));
+    }
+
+    /// Set the largest request body in bytes read by [`RequestCtx::json`]
+    /// and [`RequestCtx::form`], 2 MiB by default.
+    pub fn body_limit(&mut self, limit: usize) {
+        self.body_limit = limit;
+    }
+
+    /// Register application state such as a database pool or the config,
+    /// available to handlers and middleware through [`RequestCtx::state`]
+    /// or the [`State`] extractor. There is one value per type, registering
+    /// another replaces it.
+    pub fn state<T: Send + Sync + 'static>(&mut self, state: T) {
+        self.state.insert(state);
+    }
+
+    /// Check the routes for duplicate registrations, duplicate names and
+    /// patterns overlapping with the same specificity, where which one serves
+    /// a request only depends on where their static segments are. `run` does
+    /// this before serving.
+    ///
+    /// Patterns of the same shape differing in their parameter constraints or
+    /// host are fine, they are tried in turn, unless an earlier one without
+    /// constraints already matches everything a later one does. Identical
+    /// ones following a route with guards are fine too.
+    pub fn check_routes(&self) -> Result<(), Error> {
+        for (i, route) in self.routes.iter().enumerate() {
+            for other in &self.routes[..i] {
+                if let (Some(name), Some(other_name)) = (&route.name, &other.name) {
+                    if name == other_name {
+                        return Err(Error::new(format!(
+                            "route name {:?} used by both {} {} and {} {}",
+                            name, other.method, other.path, route.method, route.path
+                        )));
+                    }
+                }
+
+                if route.method != other.method {
+                    continue;
+                }
+
+                let segments: Vec<_> = route.path.split('/').map(Segment::parse).collect();
+                let other_segments: Vec<_> = other.path.split('/').map(Segment::parse).collect();
+
+                if Segment::same_shape(&segments, &other_segments) {
+                    if route.host != other.host || !other.guards.is_empty() {
+                        continue;
+                    }
+
+                    let constraints = segments.iter().map(Segment::constraint);
+                    let other_constraints = other_segments.iter().map(Segment::constraint);
+                    if constraints.eq(other_constraints) {
+                        return Err(Error::new(format!(
+                            "duplicate route {} {}, already registered as {}",
+                            route.method, route.path, other.path
+                        )));
+                    }
+                    // the earlier route matches everything this one does
+                    if other_segments.iter().all(|s| s.constraint().is_none()) {
+                        return Err(Error::new(format!(
+                            "route {} {} is unreachable, shadowed by {}",
+                            route.method, route.path, other.path
+                        )));
+                    }
+                } else if Segment::rank(&segments) == Segment::rank(&other_segments)
+                    && Segment::overlaps(&segments, &other_segments)
+                {
+                    return Err(Error::new(format!(
+                        "ambiguous routes {} {} and {}, both match the same paths \
+                         with equal precedence",
+                        route.method, other.path, route.path
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
 
-        let router = Arc::new(router);
-        let middlewares = Arc::new(middlewares);
+    pub async fn run(self, addr: SocketAddr) -> Result<(), Error> {
+        let app = Arc::new(self.into_app()?);
 
         let make_svc = make_service_fn(|conn: &hyper::server::conn::AddrStream| {
-            let router = router.clone();
-            let middlewares = middlewares.clone();
+            let app = app.clone();
             let remote_addr = conn.remote_addr();
 
             async move {
                 Ok::<_, Infallible>(service_fn(move |req: HyperRequest| {
-                    let router = router.clone();
-                    let middlewares = middlewares.clone();
-
-                    async move {
-                        let method = &req.method().as_str().to_uppercase();
-
-                        let mut req_params = Params::new();
-                        let endpoint = match router.get(method) {
-                            Some(router) => match router.recognize(req.uri().path()) {
-                                Ok(route_recognizer::Match { handler, params }) => {
-                                    req_params = params;
-                                    &**handler
-                                }
-                                Err(_) => &Self::handle_not_found,
-                            },
-                            None => &Self::handle_not_found,
-                        };
-
-                        let next = Next {
-                            endpoint,
-                            next_middleware: &middlewares,
-                        };
-
-                        let ctx = RequestCtx {
-                            request: req,
-                            params: req_params,
-                            remote_addr,
-                        };
-
-                        let resp = next.run(ctx).await;
-
-                        Ok::<_, Infallible>(resp)
-                    }
+                    let app = app.clone();
+
+                    async move { Ok::<_, Infallible>(app.dispatch(req, remote_addr).await) }
                 }))
             }
         });
@@ -232,18 +1622,1243 @@ impl Server {
 
         server
             .await
-            .map_err(|e| Error::new(format!("server run error: {:?}", e)))?;
+            .map_err(|e| Error::new("server run error").with_source(e))?;
 
         Ok(())
     }
 
+    /// Check and compile the routes.
+    fn into_app(self) -> Result<App, Error> {
+        self.check_routes()?;
+
+        let Self {
+            routes,
+            middlewares,
+            fallback,
+            normalize_path,
+            trailing_slash,
+            body_limit,
+            state,
+            error_handler,
+        } = self;
+
+        let urls = Arc::new(Urls::new(&routes));
+        let infos = routes
+            .iter()
+            .map(|route| route.info(middlewares.len()))
+            .collect();
+
+        let mut router = Router::new();
+        for mut route in routes {
+            route.compile()?;
+
+            let method = Method::from_bytes(route.method.as_bytes())
+                .map_err(|_| Error::new(format!("invalid method {:?}", route.method)))?;
+            let slot = router
+                .entry(&route.path)
+                .map_err(|e| Error::new(format!("invalid route {:?}: {}", route.path, e)))?
+                .entry(method);
+
+            // routes bound to a host win over the ones for any host
+            let at = match route.host {
+                Some(_) => slot
+                    .iter()
+                    .position(|route| route.host.is_none())
+                    .unwrap_or(slot.len()),
+                None => slot.len(),
+            };
+            slot.insert(at, route);
+        }
+
+        Ok(App {
+            router,
+            middlewares,
+            urls,
+            routes: infos,
+            fallback,
+            normalize_path,
+            trailing_slash,
+            body_limit,
+            state: Arc::new(state),
+            error_handler,
+        })
+    }
+
     async fn handle_not_found(_ctx: RequestCtx) -> Response {
         ResponseBuiler::with_status(hyper::StatusCode::NOT_FOUND)
     }
 }
 
+/// How a request is handled when its path only differs from a route by a
+/// trailing slash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrailingSlash {
+    /// `/users` and `/users/` are different paths.
+    #[default]
+    Strict,
+    /// Redirect with `308 Permanent Redirect` to the path having a route.
+    Redirect,
+    /// Serve the route as if the path matched it.
+    MatchBoth,
+}
+
+/// Collapse empty segments and resolve `.` and `..` ones.
+fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    let mut normalized = String::with_capacity(path.len());
+    for segment in segments {
+        normalized.push('/');
+        normalized.push_str(segment);
+    }
+    if normalized.is_empty() || path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..")
+    {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// The path with its trailing slash added or removed.
+fn toggle_trailing_slash(path: &str) -> Option<String> {
+    match path {
+        "/" => None,
+        path if path.ends_with('/') => Some(path.trim_end_matches('/').to_string()),
+        path => Some(format!("{}/", path)),
+    }
+}
+
+/// Replace the path of `uri`, keeping its query.
+fn replace_path(uri: &hyper::Uri, path: &str) -> Option<hyper::Uri> {
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path.to_string(),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    hyper::Uri::from_parts(parts).ok()
+}
+
+/// The parts of a `Server` shared by all connections once it runs.
+struct App {
+    router: Router,
+    middlewares: Vec<Arc<dyn Middleware>>,
+    urls: Arc<Urls>,
+    routes: Arc<[RouteInfo]>,
+    fallback: BoxHTTPHandler,
+    normalize_path: bool,
+    trailing_slash: TrailingSlash,
+    body_limit: usize,
+    state: Arc<Extensions>,
+    error_handler: Option<Arc<dyn ErrorHandler>>,
+}
+
+impl App {
+    async fn dispatch(&self, mut req: HyperRequest, remote_addr: SocketAddr) -> Response {
+        let mut original_uri = None;
+        if self.normalize_path {
+            let path = normalize_path(req.uri().path());
+            if path != req.uri().path() {
+                if let Some(uri) = replace_path(req.uri(), &path) {
+                    original_uri = Some(std::mem::replace(req.uri_mut(), uri));
+                }
+            }
+        }
+
+        let method = req.method();
+        let host = request_host(&req);
+        let mut path = req.uri().path();
+
+        let (mut found, mut head_fallback) = self.find(&req, method, path, host);
+
+        let mut alternate = None;
+        let mut matched_alternate = false;
+        let mut redirect = None;
+        if found.is_none()
+            && self.trailing_slash != TrailingSlash::Strict
+            && self.allowed_methods(path, host).is_empty()
+        {
+            if let Some(alt) = toggle_trailing_slash(path)
+                .filter(|alt| !self.allowed_methods(alt, host).is_empty())
+            {
+                if self.trailing_slash == TrailingSlash::Redirect {
+                    redirect = replace_path(req.uri(), &alt).map(|location| Redirect {
+                        location: location.to_string(),
+                    });
+                } else {
+                    path = alternate.get_or_insert(alt);
+                    matched_alternate = true;
+                    let (alt_found, alt_head_fallback) = self.find(&req, method, path, host);
+                    found = alt_found;
+                    head_fallback = alt_head_fallback;
+                }
+            }
+        }
+
+        let mut req_params = Params::default();
+        let mut host_params = Params::default();
+        let allow_endpoint;
+        let endpoint: &dyn HTTPHandler = match (found, &redirect) {
+            (_, Some(redirect)) => redirect,
+            (Some(found), None) => {
+                let route = found.route;
+                let source = match (matched_alternate, req.uri().path_and_query()) {
+                    (false, Some(path_and_query)) => Source::Path(path_and_query.clone()),
+                    _ => Source::Text(path.to_string()),
+                };
+                req_params = Params::new(source, route.param_names.clone(), found.captures);
+                if let (Some(pattern), Some(host)) = (&route.host_pattern, host) {
+                    host_params = pattern.params(host, found.host_captures);
+                }
+                route
+            }
+            (None, None) => {
+                let allowed = self.allowed_methods(path, host);
+                // a route for the method exists but its guards rejected the request
+                let guarded = allowed.contains(&method.as_str()) && method != Method::OPTIONS;
+                if allowed.is_empty() || guarded {
+                    &*self.fallback
+                } else {
+                    // answer OPTIONS on our own, anything else is 405
+                    let status = if method == Method::OPTIONS {
+                        hyper::StatusCode::NO_CONTENT
+                    } else {
+                        hyper::StatusCode::METHOD_NOT_ALLOWED
+                    };
+                    allow_endpoint = AllowMethods {
+                        status,
+                        allow: allowed.join(", "),
+                    };
+                    &allow_endpoint
+                }
+            }
+        };
+
+        let next = Next {
+            endpoint,
+            next_middleware: &self.middlewares,
+        };
+
+        let ctx = RequestCtx {
+            request: req,
+            params: req_params,
+            host_params,
+            remote_addr,
+            extensions: Extensions::default(),
+            original_uri,
+            urls: self.urls.clone(),
+            routes: self.routes.clone(),
+            state: self.state.clone(),
+            body_limit: self.body_limit,
+            error_handler: self.error_handler.clone(),
+        };
+
+        // errors returned by the global middleware itself
+        let mut resp = handle_error(self.error_handler.as_deref(), next.run(ctx).await);
+
+        if head_fallback {
+            strip_body(&mut resp);
+        }
+
+        resp
+    }
+
+    /// Find the route for `method` and `path`, answering HEAD with the GET
+    /// route when none is registered. The flag tells if that happened.
+    fn find(
+        &self,
+        req: &HyperRequest,
+        method: &Method,
+        path: &str,
+        host: Option<&str>,
+    ) -> (Option<Found<'_>>, bool) {
+        let found = self.recognize(req, method, path, host);
+        if found.is_none() && method == Method::HEAD {
+            let found = self.recognize(req, &Method::GET, path, host);
+            let head_fallback = found.is_some();
+            return (found, head_fallback);
+        }
+        (found, false)
+    }
+
+    /// Find the first route for `method` and `path` whose constraints, host
+    /// pattern and guards accept the request.
+    fn recognize(
+        &self,
+        req: &HyperRequest,
+        method: &Method,
+        path: &str,
+        host: Option<&str>,
+    ) -> Option<Found<'_>> {
+        self.router.find(path, |methods, captures| {
+            methods.get(method)?.iter().find_map(|route| {
+                let host_captures = route.matches(path, captures, host)?;
+                if !route.guards.iter().all(|guard| guard.check(req)) {
+                    return None;
+                }
+                Some(Found {
+                    route,
+                    captures: *captures,
+                    host_captures,
+                })
+            })
+        })
+    }
+
+    /// Collect the methods which have a route matching `path`, sorted by name.
+    fn allowed_methods(&self, path: &str, host: Option<&str>) -> Vec<&str> {
+        let mut allowed: Vec<&str> = Vec::new();
+        self.router.find(path, |methods, captures| {
+            for (method, routes) in methods.iter() {
+                if !allowed.contains(&method.as_str())
+                    && routes
+                        .iter()
+                        .any(|route| route.matches(path, captures, host).is_some())
+                {
+                    allowed.push(method.as_str());
+                }
+            }
+            // keep looking at every matching pattern
+            None::<()>
+        });
+
+        if allowed.contains(&"GET") && !allowed.contains(&"HEAD") {
+            allowed.push("HEAD");
+        }
+        if !allowed.is_empty() && !allowed.contains(&"OPTIONS") {
+            allowed.push("OPTIONS");
+        }
+        allowed.sort_unstable();
+        allowed
+    }
+}
+
+/// A route matching a request along with the captured parameters.
+struct Found<'a> {
+    route: &'a Route,
+    captures: Captures,
+    host_captures: Captures,
+}
+
+/// Drop the body of a response, keeping its `Content-Length` when known.
+fn strip_body(resp: &mut Response) {
+    use hyper::body::HttpBody;
+
+    if let Some(len) = resp.body().size_hint().exact() {
+        resp.headers_mut()
+            .entry(hyper::header::CONTENT_LENGTH)
+            .or_insert_with(|| hyper::header::HeaderValue::from(len));
+    }
+    *resp.body_mut() = hyper::Body::empty();
+}
+
+/// Endpoint redirecting to the canonical path of a route.
+struct Redirect {
+    location: String,
+}
+
+#[async_trait::async_trait]
+impl HTTPHandler for Redirect {
+    async fn handle(&self, _ctx: RequestCtx) -> Response {
+        let mut resp = ResponseBuiler::with_status(hyper::StatusCode::PERMANENT_REDIRECT);
+        if let Ok(location) = self.location.parse::<hyper::header::HeaderValue>() {
+            resp.headers_mut().insert(hyper::header::LOCATION, location);
+        }
+        resp
+    }
+}
+
+/// Endpoint used when the path exists but has no route for the requested method,
+/// it answers automatic OPTIONS requests and 405 responses.
+struct AllowMethods {
+    status: hyper::StatusCode,
+    allow: String,
+}
+
+#[async_trait::async_trait]
+impl HTTPHandler for AllowMethods {
+    async fn handle(&self, _ctx: RequestCtx) -> Response {
+        let mut resp = ResponseBuiler::with_status(self.status);
+        resp.headers_mut().insert(
+            hyper::header::ALLOW,
+            self.allow.parse::<hyper::header::HeaderValue>().unwrap(),
+        );
+        resp
+    }
+}
+
 impl Default for Server {
     fn default() -> Self {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn body(resp: Response) -> String {
+        let bytes = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn error_responses_hide_server_error_messages() {
+        let resp = Error::new("db password=hunter2 failed").into_response();
+        assert_eq!(resp.status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body(resp).await, "Internal Server Error");
+
+        let resp = Error::new("no such user")
+            .with_status(hyper::StatusCode::NOT_FOUND)
+            .into_response();
+        assert_eq!(resp.status(), hyper::StatusCode::NOT_FOUND);
+        assert_eq!(body(resp).await, "no such user");
+    }
+
+    async fn ok(_ctx: RequestCtx) -> Response {
+        ResponseBuiler::with_status(hyper::StatusCode::OK)
+    }
+
+    /// Dispatch a request to `srv`, returning the status and body.
+    async fn request(srv: Server, method: &str, uri: &str) -> (hyper::StatusCode, String) {
+        let resp = send(&srv.into_app().unwrap(), method, uri, &[]).await;
+        (resp.status(), body(resp).await)
+    }
+
+    async fn send(app: &App, method: &str, uri: &str, headers: &[(&str, &str)]) -> Response {
+        send_body(app, method, uri, headers, hyper::Body::empty()).await
+    }
+
+    async fn send_body(
+        app: &App,
+        method: &str,
+        uri: &str,
+        headers: &[(&str, &str)],
+        body: hyper::Body,
+    ) -> Response {
+        let mut req = hyper::Request::builder().method(method).uri(uri);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let req = req.body(body).unwrap();
+        app.dispatch(req, "127.0.0.1:1234".parse().unwrap()).await
+    }
+
+    /// A body streamed in chunks of `size` bytes, without a length.
+    fn chunked(data: Vec<u8>, size: usize) -> hyper::Body {
+        let (mut tx, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            for chunk in data.chunks(size) {
+                let chunk = hyper::body::Bytes::copy_from_slice(chunk);
+                if tx.send_data(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+        body
+    }
+
+    async fn path(ctx: RequestCtx) -> String {
+        ctx.request.uri().path().to_string()
+    }
+
+    #[tokio::test]
+    async fn mounted_root_is_the_mount_point() {
+        let mut billing = Server::new();
+        billing.get("/", path);
+        billing.get("/invoices", path);
+
+        let mut srv = Server::new();
+        srv.mount("/billing", billing);
+        assert_eq!(srv.routes()[0].path, "/billing");
+
+        let app = srv.into_app().unwrap();
+        let resp = send(&app, "GET", "/billing", &[]).await;
+        assert_eq!(body(resp).await, "/");
+
+        let mut billing = Server::new();
+        billing.get("/", path);
+        billing.get("/invoices", path);
+        let mut srv = Server::new();
+        srv.mount("/billing/", billing);
+        assert_eq!(
+            request(srv, "GET", "/billing/invoices").await.1,
+            "/invoices"
+        );
+    }
+
+    #[tokio::test]
+    async fn other_methods_get_405_with_allow() {
+        let mut srv = Server::new();
+        srv.get("/users", ok);
+        srv.post("/users", ok);
+        let app = srv.into_app().unwrap();
+
+        let resp = send(&app, "DELETE", "/users", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            resp.headers()[hyper::header::ALLOW],
+            "GET, HEAD, OPTIONS, POST"
+        );
+
+        let mut srv = Server::new();
+        srv.get("/users", ok);
+        let (status, _) = request(srv, "DELETE", "/nope").await;
+        assert_eq!(status, hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn head_falls_back_to_get() {
+        async fn hello(_ctx: RequestCtx) -> &'static str {
+            "hello"
+        }
+        async fn head(_ctx: RequestCtx) -> hyper::StatusCode {
+            hyper::StatusCode::NO_CONTENT
+        }
+
+        let mut srv = Server::new();
+        srv.get("/hello", hello);
+        let app = srv.into_app().unwrap();
+        let resp = send(&app, "HEAD", "/hello", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        assert_eq!(resp.headers()[hyper::header::CONTENT_LENGTH], "5");
+        assert_eq!(body(resp).await, "");
+
+        // an explicit HEAD route wins
+        let mut srv = Server::new();
+        srv.get("/hello", hello);
+        srv.head("/hello", head);
+        let (status, _) = request(srv, "HEAD", "/hello").await;
+        assert_eq!(status, hyper::StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn options_are_answered_automatically() {
+        let mut srv = Server::new();
+        srv.get("/users", ok);
+        srv.post("/users", ok);
+        let app = srv.into_app().unwrap();
+        let resp = send(&app, "OPTIONS", "/users", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::NO_CONTENT);
+        assert_eq!(
+            resp.headers()[hyper::header::ALLOW],
+            "GET, HEAD, OPTIONS, POST"
+        );
+
+        // unless there is a route for them
+        let mut srv = Server::new();
+        srv.get("/users", ok);
+        srv.options("/users", path);
+        let (status, body) = request(srv, "OPTIONS", "/users").await;
+        assert_eq!((status, body.as_str()), (hyper::StatusCode::OK, "/users"));
+    }
+
+    #[tokio::test]
+    async fn trailing_slash_policies() {
+        let server = |policy| {
+            let mut srv = Server::new();
+            srv.trailing_slash(policy);
+            srv.get("/users", path);
+            srv
+        };
+
+        let (status, _) = request(server(TrailingSlash::Strict), "GET", "/users/").await;
+        assert_eq!(status, hyper::StatusCode::NOT_FOUND);
+
+        let app = server(TrailingSlash::Redirect).into_app().unwrap();
+        let resp = send(&app, "GET", "/users/?page=2", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(resp.headers()[hyper::header::LOCATION], "/users?page=2");
+
+        let (status, body) = request(server(TrailingSlash::MatchBoth), "GET", "/users/").await;
+        assert_eq!((status, body.as_str()), (hyper::StatusCode::OK, "/users/"));
+    }
+
+    #[tokio::test]
+    async fn normalized_paths() {
+        let mut srv = Server::new();
+        srv.normalize_path(true);
+        srv.get("/users/:id", path);
+        let (status, body) = request(srv, "GET", "//users/./a/../42").await;
+        assert_eq!(
+            (status, body.as_str()),
+            (hyper::StatusCode::OK, "/users/42")
+        );
+    }
+
+    #[tokio::test]
+    async fn host_routes() {
+        async fn tenant(ctx: RequestCtx) -> String {
+            ctx.host_params.find("tenant").unwrap_or("-").to_string()
+        }
+
+        let mut srv = Server::new();
+        srv.get("/", path);
+        srv.host("{tenant}.example.com", |g| {
+            g.get("/", tenant);
+        });
+        srv.get("/admin", path).host("admin.example.com");
+        let app = &srv.into_app().unwrap();
+        let get = |host, uri| async move {
+            let resp = send(app, "GET", uri, &[("host", host)]).await;
+            (resp.status(), body(resp).await)
+        };
+
+        // host routes win over the ones for any host
+        assert_eq!(get("acme.example.com:8080", "/").await.1, "acme");
+        assert_eq!(get("example.org", "/").await.1, "/");
+        assert_eq!(
+            get("admin.example.com", "/admin").await.0,
+            hyper::StatusCode::OK
+        );
+        assert_eq!(
+            get("acme.example.com", "/admin").await.0,
+            hyper::StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn guards_select_between_routes() {
+        async fn v2(_ctx: RequestCtx) -> &'static str {
+            "v2"
+        }
+        async fn json(_ctx: RequestCtx) -> &'static str {
+            "json"
+        }
+
+        let mut srv = Server::new();
+        srv.post("/users", v2)
+            .guard(guard::header("X-Api-Version", "2"));
+        srv.post("/users", json)
+            .guard(guard::content_type("application/json"));
+        srv.get("/items", ok).guard(guard::query("format", "csv"));
+        let app = &srv.into_app().unwrap();
+
+        let post = |headers| async move {
+            let resp = send(app, "POST", "/users", headers).await;
+            (resp.status(), body(resp).await)
+        };
+        assert_eq!(post(&[("x-api-version", "2")]).await.1, "v2");
+        assert_eq!(
+            post(&[("content-type", "application/json")]).await.1,
+            "json"
+        );
+        // the first route whose guards pass wins
+        assert_eq!(
+            post(&[("x-api-version", "2"), ("content-type", "application/json")])
+                .await
+                .1,
+            "v2"
+        );
+        // routes rejected by their guards fall through to the fallback
+        assert_eq!(post(&[]).await.0, hyper::StatusCode::NOT_FOUND);
+        let resp = send(app, "HEAD", "/items", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::NOT_FOUND);
+        let resp = send(app, "HEAD", "/items?format=csv", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        // OPTIONS still lists the method
+        let resp = send(app, "OPTIONS", "/users", &[]).await;
+        assert_eq!(resp.headers()[hyper::header::ALLOW], "OPTIONS, POST");
+    }
+
+    #[tokio::test]
+    async fn error_handler_sees_handler_errors_and_rejections() {
+        async fn fail(Path(n): Path<u32>) -> Result<String, Error> {
+            Err(Error::new(format!("failed {}", n)).with_status(hyper::StatusCode::CONFLICT))
+        }
+
+        let mut srv = Server::new();
+        srv.get("/fail/:n", fail);
+        srv.error_handler(|err: Error| {
+            let rejected = err.downcast_ref::<ParamError>().is_some();
+            (err.status(), format!("handled {} {}", rejected, err))
+        });
+        let app = &srv.into_app().unwrap();
+
+        let resp = send(app, "GET", "/fail/1", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::CONFLICT);
+        assert_eq!(body(resp).await, "handled false failed 1");
+
+        let resp = send(app, "GET", "/fail/x", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::BAD_REQUEST);
+        assert!(body(resp)
+            .await
+            .starts_with("handled true invalid path parameter"));
+    }
+
+    #[tokio::test]
+    async fn error_handler_sees_middleware_errors() {
+        struct Deny(&'static str);
+
+        #[async_trait::async_trait]
+        impl Middleware for Deny {
+            async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response {
+                if ctx.request.uri().path().ends_with(self.0) {
+                    return Error::new(format!("denied by {}", self.0))
+                        .with_status(hyper::StatusCode::FORBIDDEN)
+                        .into_response();
+                }
+                next.run(ctx).await
+            }
+        }
+
+        let mut srv = Server::new();
+        srv.middleware(Deny("/global"));
+        srv.scope("/s", |s| {
+            s.middleware(Deny("/scope"));
+            s.get("/*rest", ok).with(Deny("/route"));
+        });
+        srv.error_handler(|err: Error| (err.status(), format!("handled {}", err)));
+        let app = &srv.into_app().unwrap();
+
+        for name in ["global", "scope", "route"] {
+            let resp = send(app, "GET", &format!("/s/{}", name), &[]).await;
+            assert_eq!(resp.status(), hyper::StatusCode::FORBIDDEN);
+            assert!(resp.extensions().get::<Error>().is_none());
+            assert_eq!(body(resp).await, format!("handled denied by /{}", name));
+        }
+    }
+
+    /// Appends its name to the [`Trace`] of the request.
+    struct Tag(&'static str);
+
+    #[derive(Clone, Default)]
+    struct Trace(Vec<&'static str>);
+
+    #[async_trait::async_trait]
+    impl Middleware for Tag {
+        async fn handle<'a>(&'a self, mut ctx: RequestCtx, next: Next<'a>) -> Response {
+            let mut trace = ctx.extensions.remove::<Trace>().unwrap_or_default();
+            trace.0.push(self.0);
+            ctx.extensions.insert(trace);
+            next.run(ctx).await
+        }
+    }
+
+    async fn trace(ctx: RequestCtx) -> String {
+        let trace = ctx.extensions.get::<Trace>().cloned().unwrap_or_default();
+        trace.0.join(" ")
+    }
+
+    #[tokio::test]
+    async fn scopes() {
+        let mut srv = Server::new();
+        srv.middleware(Tag("global"));
+        srv.get("/", trace);
+        srv.scope("/api", |api| {
+            api.middleware(Tag("api"));
+            api.get("/", trace);
+            api.scope("/v1/", |v1| {
+                v1.middleware(Tag("v1"));
+                v1.get("/", trace);
+                v1.get("/users", trace);
+            });
+        });
+        srv.get("/other", trace);
+        let app = &srv.into_app().unwrap();
+
+        for (uri, expected) in [
+            ("/", "global"),
+            ("/api", "global api"),
+            ("/api/v1", "global api v1"),
+            ("/api/v1/users", "global api v1"),
+            ("/other", "global"),
+        ] {
+            let resp = send(app, "GET", uri, &[]).await;
+            assert_eq!(resp.status(), hyper::StatusCode::OK, "{}", uri);
+            assert_eq!(body(resp).await, expected, "{}", uri);
+        }
+        // like any other route under the strict trailing slash policy
+        let resp = send(app, "GET", "/api/", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn middleware_order() {
+        let mut srv = Server::new();
+        srv.middleware(Tag("global 1"));
+        srv.scope("/api", |api| {
+            api.middleware(Tag("scope"));
+            api.get("/users", trace)
+                .with(Tag("route 1"))
+                .with(Tag("route 2"));
+            api.get("/plain", trace);
+        });
+        srv.middleware(Tag("global 2"));
+        let app = &srv.into_app().unwrap();
+
+        let resp = send(app, "GET", "/api/users", &[]).await;
+        assert_eq!(body(resp).await, "global 1 global 2 scope route 1 route 2");
+        let resp = send(app, "GET", "/api/plain", &[]).await;
+        assert_eq!(body(resp).await, "global 1 global 2 scope");
+    }
+
+    #[tokio::test]
+    async fn fallback_runs_inside_global_middleware() {
+        async fn not_found(ctx: RequestCtx) -> (hyper::StatusCode, String) {
+            let trace = trace(ctx).await;
+            (
+                hyper::StatusCode::NOT_FOUND,
+                format!("nothing here, {}", trace),
+            )
+        }
+
+        let mut srv = Server::new();
+        srv.middleware(Tag("global"));
+        srv.scope("/api", |api| {
+            api.middleware(Tag("scope"));
+            api.get("/users", trace);
+        });
+        srv.fallback(not_found);
+        let app = &srv.into_app().unwrap();
+
+        for uri in ["/", "/api", "/api/posts"] {
+            let resp = send(app, "GET", uri, &[]).await;
+            assert_eq!(resp.status(), hyper::StatusCode::NOT_FOUND, "{}", uri);
+            assert_eq!(body(resp).await, "nothing here, global", "{}", uri);
+        }
+        let resp = send(app, "GET", "/api/users", &[]).await;
+        assert_eq!(body(resp).await, "global scope");
+    }
+
+    struct Pass;
+
+    #[async_trait::async_trait]
+    impl Middleware for Pass {
+        async fn handle<'a>(&'a self, ctx: RequestCtx, next: Next<'a>) -> Response {
+            next.run(ctx).await
+        }
+    }
+
+    #[tokio::test]
+    async fn route_listing_counts_user_middleware() {
+        let mut billing = Server::new();
+        billing.middleware(Pass);
+        billing.get("/invoices", path).with(Pass);
+
+        let mut srv = Server::new();
+        srv.middleware(Pass);
+        srv.mount("/billing", billing);
+        srv.get("/_routes", RouteListing::Json);
+        assert_eq!(srv.routes()[0].middlewares, 3);
+
+        let app = srv.into_app().unwrap();
+        let resp = send(&app, "GET", "/_routes", &[]).await;
+        assert_eq!(
+            resp.headers()[hyper::header::CONTENT_TYPE],
+            "application/json"
+        );
+        let routes: serde_json::Value = serde_json::from_str(&body(resp).await).unwrap();
+        assert_eq!(routes[0]["path"], "/billing/invoices");
+        assert_eq!(routes[0]["middlewares"], 3);
+        assert_eq!(routes[1]["middlewares"], 1);
+    }
+
+    #[derive(Clone)]
+    struct User(&'static str);
+
+    struct SetUser;
+
+    #[async_trait::async_trait]
+    impl Middleware for SetUser {
+        async fn handle<'a>(&'a self, mut ctx: RequestCtx, next: Next<'a>) -> Response {
+            ctx.extensions.insert(User("ada"));
+            next.run(ctx).await
+        }
+    }
+
+    #[tokio::test]
+    async fn extensions_are_cloned_into_handlers() {
+        async fn both(Extension(a): Extension<User>, Extension(b): Extension<User>) -> String {
+            format!("{} {}", a.0, b.0)
+        }
+        async fn missing(Extension(n): Extension<u32>) -> String {
+            n.to_string()
+        }
+
+        let mut srv = Server::new();
+        srv.middleware(SetUser);
+        srv.get("/both", both);
+        let app = srv.into_app().unwrap();
+
+        let resp = send(&app, "GET", "/both", &[]).await;
+        assert_eq!(body(resp).await, "ada ada");
+
+        let mut srv = Server::new();
+        srv.get("/missing", missing);
+        let (status, _) = request(srv, "GET", "/missing").await;
+        assert_eq!(status, hyper::StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn multipart_uploads_are_not_bound_by_the_body_limit() {
+        async fn upload(mut ctx: RequestCtx) -> Result<String, MultipartError> {
+            let dir = std::env::temp_dir();
+            let path = dir.join(format!("tinyweb-upload-{}", std::process::id()));
+            let mut multipart = ctx.multipart()?;
+            let mut field = multipart.next_field().await?.unwrap();
+            let size = field.save(&path).await;
+            let saved = std::fs::metadata(&path).map(|m| m.len());
+            let _ = std::fs::remove_file(&path);
+            Ok(format!("{} {}", size?, saved.unwrap()))
+        }
+
+        let len = DEFAULT_BODY_LIMIT + 1024 * 1024;
+        let mut data = b"--b0undary\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"big.bin\"\r\n\
+            \r\n"
+            .to_vec();
+        data.extend((0..len).map(|i| (i % 251) as u8));
+        data.extend_from_slice(b"\r\n--b0undary--\r\n");
+
+        let mut srv = Server::new();
+        srv.post("/upload", upload);
+        let app = srv.into_app().unwrap();
+        let content_type = [("content-type", "multipart/form-data; boundary=b0undary")];
+        let resp = send_body(
+            &app,
+            "POST",
+            "/upload",
+            &content_type,
+            chunked(data, 64 * 1024),
+        )
+        .await;
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        assert_eq!(body(resp).await, format!("{} {}", len, len));
+    }
+
+    #[tokio::test]
+    async fn constrained_params() {
+        async fn id(ctx: RequestCtx) -> Result<String, ParamError> {
+            let id: u64 = ctx.param("id")?;
+            Ok(format!("id {}", id))
+        }
+        async fn name(ctx: RequestCtx) -> Result<String, ParamError> {
+            let name: String = ctx.param("name")?;
+            Ok(format!("name {}", name))
+        }
+
+        let mut srv = Server::new();
+        srv.get("/users/{id:u64}", id);
+        srv.get("/posts/{slug:[a-z0-9-]+}", path);
+        srv.get("/items/:id", id);
+        srv.get("/tags/{id:u64}", id);
+        srv.get("/tags/:name", name);
+        let app = &srv.into_app().unwrap();
+
+        for (uri, status, expected) in [
+            ("/users/42", hyper::StatusCode::OK, "id 42"),
+            ("/users/abc", hyper::StatusCode::NOT_FOUND, ""),
+            ("/users/-1", hyper::StatusCode::NOT_FOUND, ""),
+            ("/posts/hello-2", hyper::StatusCode::OK, "/posts/hello-2"),
+            ("/posts/Hello", hyper::StatusCode::NOT_FOUND, ""),
+            ("/tags/7", hyper::StatusCode::OK, "id 7"),
+            ("/tags/rust", hyper::StatusCode::OK, "name rust"),
+        ] {
+            let resp = send(app, "GET", uri, &[]).await;
+            assert_eq!(resp.status(), status, "{}", uri);
+            assert_eq!(body(resp).await, expected, "{}", uri);
+        }
+
+        // unconstrained, so the handler rejects it
+        let resp = send(app, "GET", "/items/abc", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::BAD_REQUEST);
+        assert!(body(resp).await.contains("\"abc\""));
+    }
+
+    #[tokio::test]
+    async fn json_bodies() {
+        #[derive(serde::Deserialize)]
+        struct NewUser {
+            name: String,
+            age: u8,
+        }
+
+        async fn create(mut ctx: RequestCtx) -> Result<String, JsonError> {
+            let user: NewUser = ctx.json().await?;
+            Ok(format!("{} {}", user.name, user.age))
+        }
+
+        let mut srv = Server::new();
+        srv.post("/users", create);
+        srv.body_limit(32);
+        let app = &srv.into_app().unwrap();
+
+        let json = [("content-type", "application/json")];
+        for (headers, data, status, expected) in [
+            (&json[..], r#"{"name":"ada","age":36}"#, 200, "ada 36"),
+            (
+                &[("content-type", "application/vnd.api+json; charset=utf-8")],
+                r#"{"name":"ada","age":36}"#,
+                200,
+                "ada 36",
+            ),
+            (&[], "{}", 415, "expected a JSON body, got no content type"),
+            (
+                &[("content-type", "text/plain")],
+                "{}",
+                415,
+                "expected a JSON body, got \"text/plain\"",
+            ),
+            (&json, r#"{"name":"#, 400, "malformed JSON"),
+            (&json, r#"{"name":"ada","age":36} x"#, 400, "malformed JSON"),
+            (
+                &json,
+                r#"{"name":"ada","age":"x"}"#,
+                422,
+                "invalid JSON field \"age\"",
+            ),
+            (
+                &json,
+                r#"{"name":"ada"}"#,
+                422,
+                "invalid JSON: missing field",
+            ),
+        ] {
+            let resp = send_body(app, "POST", "/users", headers, data.into()).await;
+            assert_eq!(resp.status().as_u16(), status, "{}", data);
+            assert!(body(resp).await.starts_with(expected), "{}", data);
+        }
+
+        // refused from the length alone
+        let headers = [
+            ("content-type", "application/json"),
+            ("content-length", "33"),
+        ];
+        let resp = send_body(app, "POST", "/users", &headers, hyper::Body::empty()).await;
+        assert_eq!(resp.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+
+        let data = format!(r#"{{"name":"{}","age":36}}"#, "a".repeat(20));
+        let resp = send_body(app, "POST", "/users", &json, chunked(data.into(), 4)).await;
+        assert_eq!(resp.status(), hyper::StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(body(resp).await, "body larger than 32 bytes");
+    }
+
+    #[tokio::test]
+    async fn url_for() {
+        async fn link(ctx: RequestCtx) -> Result<String, Error> {
+            Ok(ctx.url_for("user", [("id", 42)])?)
+        }
+
+        let mut srv = Server::new();
+        srv.get("/users/{id:u64}", link).name("user");
+        srv.get("/users/:id/posts/:title", ok).name("post");
+        srv.get("/files/*path", ok).name("file");
+        srv.scope("/api", |api| {
+            api.get("/", ok).name("api");
+        });
+
+        let urls = Urls::new(&srv.routes);
+        let no_params: [(&str, &str); 0] = [];
+        assert_eq!(urls.url_for("api", no_params).unwrap(), "/api");
+        assert_eq!(
+            urls.url_for("post", [("id", "7"), ("title", "a b/c")])
+                .unwrap(),
+            "/users/7/posts/a%20b%2Fc"
+        );
+        assert_eq!(
+            urls.url_for("file", [("path", "docs/a b.txt")]).unwrap(),
+            "/files/docs/a%20b.txt"
+        );
+        assert!(matches!(
+            urls.url_for("nope", no_params),
+            Err(UrlError::UnknownRoute(name)) if name == "nope"
+        ));
+        assert!(matches!(
+            urls.url_for("post", [("id", "7")]),
+            Err(UrlError::MissingParam { route, param }) if route == "post" && param == "title"
+        ));
+
+        assert_eq!(request(srv, "GET", "/users/1").await.1, "/users/42");
+    }
+
+    #[tokio::test]
+    async fn query_strings() {
+        #[derive(serde::Deserialize)]
+        struct Page {
+            page: u32,
+            per_page: Option<u32>,
+        }
+
+        async fn list(ctx: RequestCtx) -> Result<String, QueryError> {
+            let page: Page = ctx.query()?;
+            Ok(format!("{} {:?}", page.page, page.per_page))
+        }
+
+        let mut srv = Server::new();
+        srv.get("/items", list);
+        let app = &srv.into_app().unwrap();
+
+        let resp = send(app, "GET", "/items?page=2&per_page=50", &[]).await;
+        assert_eq!(body(resp).await, "2 Some(50)");
+        let resp = send(app, "GET", "/items?page=2", &[]).await;
+        assert_eq!(body(resp).await, "2 None");
+
+        let resp = send(app, "GET", "/items?page=2&per_page=x", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::BAD_REQUEST);
+        assert!(body(resp)
+            .await
+            .starts_with("invalid query parameter \"per_page\""));
+
+        let resp = send(app, "GET", "/items", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body(resp).await,
+            "invalid query string: missing field `page`"
+        );
+    }
+
+    #[tokio::test]
+    async fn form_bodies() {
+        #[derive(serde::Deserialize)]
+        struct Login {
+            user: String,
+            remember: bool,
+        }
+
+        async fn login(mut ctx: RequestCtx) -> Result<String, FormError> {
+            let login: Login = ctx.form().await?;
+            Ok(format!("{} {}", login.user, login.remember))
+        }
+
+        let mut srv = Server::new();
+        srv.post("/login", login);
+        srv.body_limit(32);
+        let app = &srv.into_app().unwrap();
+
+        let form = [(
+            "content-type",
+            "application/x-www-form-urlencoded; charset=UTF-8",
+        )];
+        for (headers, data, status, expected) in [
+            (&form[..], "user=a+b%21&remember=true", 200, "a b! true"),
+            (
+                &[],
+                "user=a",
+                415,
+                "expected a form body, got no content type",
+            ),
+            (
+                &[("content-type", "application/json")],
+                "{}",
+                415,
+                "expected a form body, got \"application/json\"",
+            ),
+            (
+                &form,
+                "user=a&remember=maybe",
+                400,
+                "invalid form field \"remember\"",
+            ),
+            (
+                &form,
+                "user=a",
+                400,
+                "invalid form: missing field `remember`",
+            ),
+            (&form, &"user=a".repeat(6), 413, "body larger than 32 bytes"),
+        ] {
+            let resp = send_body(app, "POST", "/login", headers, data.to_string().into()).await;
+            assert_eq!(resp.status().as_u16(), status, "{}", data);
+            assert!(body(resp).await.starts_with(expected), "{}", data);
+        }
+    }
+
+    #[tokio::test]
+    async fn application_state() {
+        #[derive(Clone)]
+        struct Config(&'static str);
+
+        async fn name(State(config): State<Config>) -> &'static str {
+            config.0
+        }
+        async fn count(ctx: RequestCtx) -> String {
+            ctx.state::<u32>()
+                .map_or("none".to_string(), u32::to_string)
+        }
+
+        let mut billing = Server::new();
+        billing.state(7u32);
+        billing.get("/count", count);
+
+        let mut srv = Server::new();
+        srv.state(Config("old"));
+        srv.state(Config("tinyweb"));
+        srv.get("/name", name);
+        srv.mount("/billing", billing);
+        srv.error_handler(|err: Error| (err.status(), err.to_string()));
+        let app = &srv.into_app().unwrap();
+
+        let resp = send(app, "GET", "/name", &[]).await;
+        assert_eq!(body(resp).await, "tinyweb");
+        let resp = send(app, "GET", "/billing/count", &[]).await;
+        assert_eq!(body(resp).await, "7");
+
+        let mut srv = Server::new();
+        srv.get("/name", name);
+        srv.get("/count", count);
+        srv.error_handler(|err: Error| (err.status(), err.to_string()));
+        let app = &srv.into_app().unwrap();
+
+        let resp = send(app, "GET", "/name", &[]).await;
+        assert_eq!(resp.status(), hyper::StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body(resp).await.starts_with("no application state of type"));
+        let resp = send(app, "GET", "/count", &[]).await;
+        assert_eq!(body(resp).await, "none");
+    }
+
+    #[test]
+    fn check_routes_reports_shadowed_routes() {
+        let mut srv = Server::new();
+        srv.get("/users/:id", ok);
+        srv.get("/users/{id:u64}", ok);
+        assert!(srv.check_routes().is_err());
+
+        // the constrained route first is fine
+        let mut srv = Server::new();
+        srv.get("/users/{id:u64}", ok);
+        srv.get("/users/:id", ok);
+        assert!(srv.check_routes().is_ok());
+
+        let mut srv = Server::new();
+        srv.get("/users/:id", ok)
+            .guard(guard::header("X-Api-Version", "2"));
+        srv.get("/users/{id:u64}", ok);
+        assert!(srv.check_routes().is_ok());
+
+        let mut srv = Server::new();
+        srv.get("/users/:id", ok).host("api.example.com");
+        srv.get("/users/{id:u64}", ok);
+        assert!(srv.check_routes().is_ok());
+    }
+
+    #[test]
+    fn check_routes_ignores_leading_slashes() {
+        let mut srv = Server::new();
+        srv.get("/a/:x", ok);
+        srv.get("a/:y", ok);
+        assert!(srv.check_routes().is_err());
+        assert_eq!(srv.routes()[1].path, "/a/:y");
+    }
+
+    #[test]
+    fn error_chain() {
+        let io = std::io::Error::other("db down");
+        let err = Error::new("loading user").with_source(io);
+        assert_eq!(err.to_string(), "loading user");
+        assert_eq!(format!("{:#}", err), "loading user: db down");
+
+        let err = Error::from(ParamError::Missing("id".to_string()));
+        assert_eq!(format!("{:#}", err), err.to_string());
+        assert!(err.downcast_ref::<ParamError>().is_some());
+    }
+}
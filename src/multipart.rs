@@ -0,0 +1,649 @@
+//! Streaming `multipart/form-data` parsing, see [`RequestCtx::multipart`].
+//!
+//! The body is read as the parts are consumed, only what is needed to find
+//! the next boundary is buffered.
+//!
+//! [`RequestCtx::multipart`]: crate::RequestCtx::multipart
+
+use std::fmt;
+use std::path::Path;
+
+use hyper::body::{Bytes, HttpBody};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use tokio::io::AsyncWriteExt;
+
+use crate::IntoResponse;
+
+/// Largest header block of a part.
+const MAX_HEADERS_SIZE: usize = 16 * 1024;
+
+/// Error returned while reading a multipart body.
+#[derive(Debug)]
+pub enum MultipartError {
+    /// The `Content-Type` is not `multipart/form-data` with a boundary, holds
+    /// the one sent if any.
+    ContentType(Option<String>),
+    /// The body is larger than the total limit.
+    TooLarge { limit: usize },
+    /// A part is larger than the part limit.
+    PartTooLarge { name: Option<String>, limit: usize },
+    /// Reading the body failed.
+    Read(hyper::Error),
+    /// The body is not valid multipart.
+    Malformed(&'static str),
+    /// Writing a part to disk failed.
+    Io(std::io::Error),
+}
+
+impl MultipartError {
+    /// `415 Unsupported Media Type`, `413 Payload Too Large` for the limits,
+    /// `500 Internal Server Error` if writing to disk failed and `400 Bad
+    /// Request` otherwise.
+    pub fn status(&self) -> hyper::StatusCode {
+        match self {
+            MultipartError::ContentType(_) => hyper::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            MultipartError::TooLarge { .. } | MultipartError::PartTooLarge { .. } => {
+                hyper::StatusCode::PAYLOAD_TOO_LARGE
+            }
+            MultipartError::Read(_) | MultipartError::Malformed(_) => {
+                hyper::StatusCode::BAD_REQUEST
+            }
+            MultipartError::Io(_) => hyper::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl IntoResponse for MultipartError {
+    fn into_response(self) -> crate::Response {
+        crate::Error::from(self).into_response()
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::ContentType(Some(content_type)) => {
+                write!(f, "expected a multipart body, got {:?}", content_type)
+            }
+            MultipartError::ContentType(None) => {
+                f.write_str("expected a multipart body, got no content type")
+            }
+            MultipartError::TooLarge { limit } => write!(f, "body larger than {} bytes", limit),
+            MultipartError::PartTooLarge {
+                name: Some(name),
+                limit,
+            } => write!(f, "part {:?} larger than {} bytes", name, limit),
+            MultipartError::PartTooLarge { name: None, limit } => {
+                write!(f, "part larger than {} bytes", limit)
+            }
+            MultipartError::Read(e) => write!(f, "failed to read body: {}", e),
+            MultipartError::Malformed(reason) => write!(f, "malformed multipart body: {}", reason),
+            MultipartError::Io(e) => write!(f, "failed to save part: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for MultipartError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MultipartError::Read(e) => Some(e),
+            MultipartError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// In the preamble or a part, up to the next delimiter.
+    Body,
+    /// Right after a delimiter, either `--` closing the body or a line break.
+    Delimiter,
+    /// At the line break before the headers of a part.
+    Headers,
+    Done,
+}
+
+/// A `multipart/form-data` body, read one part at a time:
+///
+/// ```no_run
+/// # use tinyweb::{MultipartError, RequestCtx};
+/// # async fn handler(mut ctx: RequestCtx) -> Result<(), MultipartError> {
+/// # let upload_dir = std::path::Path::new("/tmp");
+/// # let new_upload_id = || "upload";
+/// let mut multipart = ctx.multipart()?;
+/// multipart
+///     .total_limit(1024 * 1024 * 1024)
+///     .part_limit(512 * 1024 * 1024);
+///
+/// while let Some(mut field) = multipart.next_field().await? {
+///     match field.file_name() {
+///         Some(_) => {
+///             field.save(upload_dir.join(new_upload_id())).await?;
+///         }
+///         None => {
+///             let value = field.text().await?;
+///         }
+///     }
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Multipart {
+    body: hyper::Body,
+    /// `\r\n--boundary`
+    delimiter: Vec<u8>,
+    buf: Vec<u8>,
+    pos: usize,
+    state: State,
+    read: usize,
+    total_limit: usize,
+    part_limit: usize,
+}
+
+impl Multipart {
+    pub(crate) fn new(body: hyper::Body, boundary: &str) -> Self {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend_from_slice(boundary.as_bytes());
+
+        Multipart {
+            body,
+            delimiter,
+            // lets the first delimiter be found like the others
+            buf: b"\r\n".to_vec(),
+            pos: 0,
+            state: State::Body,
+            read: 0,
+            total_limit: usize::MAX,
+            part_limit: usize::MAX,
+        }
+    }
+
+    /// The boundary of a `multipart/form-data` content type.
+    pub(crate) fn boundary(content_type: &str) -> Option<String> {
+        if !crate::guard::essence(content_type).eq_ignore_ascii_case("multipart/form-data") {
+            return None;
+        }
+
+        header_params(content_type)
+            .into_iter()
+            .find(|(key, _)| key == "boundary")
+            .map(|(_, boundary)| boundary)
+            .filter(|boundary| !boundary.is_empty() && boundary.len() <= 70)
+    }
+
+    /// Set the largest body in bytes, unbounded by default.
+    pub fn total_limit(&mut self, limit: usize) -> &mut Self {
+        self.total_limit = limit;
+        self
+    }
+
+    /// Set the largest part in bytes, only bound by the total limit by
+    /// default.
+    pub fn part_limit(&mut self, limit: usize) -> &mut Self {
+        self.part_limit = limit;
+        self
+    }
+
+    /// The next part, skipping what is left of the current one.
+    pub async fn next_field(&mut self) -> Result<Option<Field<'_>>, MultipartError> {
+        loop {
+            match self.state {
+                State::Body => while self.body_chunk().await?.is_some() {},
+                State::Delimiter => {
+                    self.fill_to(2).await?;
+                    match &self.buf[self.pos..self.pos + 2] {
+                        b"--" => self.state = State::Done,
+                        b"\r\n" => self.state = State::Headers,
+                        _ => return Err(MultipartError::Malformed("invalid boundary line")),
+                    }
+                }
+                State::Headers => {
+                    let headers = self.headers().await?;
+                    self.state = State::Body;
+                    return Ok(Some(Field::new(self, headers)));
+                }
+                State::Done => return Ok(None),
+            }
+        }
+    }
+
+    /// Read another chunk of the body into the buffer, `false` at its end.
+    async fn fill(&mut self) -> Result<bool, MultipartError> {
+        let chunk = match self.body.data().await {
+            Some(chunk) => chunk.map_err(MultipartError::Read)?,
+            None => return Ok(false),
+        };
+
+        self.read += chunk.len();
+        if self.read > self.total_limit {
+            return Err(MultipartError::TooLarge {
+                limit: self.total_limit,
+            });
+        }
+
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+        self.buf.extend_from_slice(&chunk);
+        Ok(true)
+    }
+
+    /// Buffer at least `len` unconsumed bytes.
+    async fn fill_to(&mut self, len: usize) -> Result<(), MultipartError> {
+        while self.buf.len() - self.pos < len {
+            if !self.fill().await? {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+        }
+        Ok(())
+    }
+
+    /// The next piece of the current part, `None` once the delimiter ending
+    /// it is reached.
+    async fn body_chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        if self.state != State::Body {
+            return Ok(None);
+        }
+
+        loop {
+            let data = &self.buf[self.pos..];
+            if let Some(i) = find(data, &self.delimiter) {
+                let chunk = Bytes::copy_from_slice(&data[..i]);
+                self.pos += i + self.delimiter.len();
+                self.state = State::Delimiter;
+                return Ok(Some(chunk).filter(|chunk| !chunk.is_empty()));
+            }
+
+            // hold back what could be the start of the delimiter
+            let safe = data.len().saturating_sub(self.delimiter.len() - 1);
+            if safe > 0 {
+                let chunk = Bytes::copy_from_slice(&data[..safe]);
+                self.pos += safe;
+                return Ok(Some(chunk));
+            }
+
+            if !self.fill().await? {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+        }
+    }
+
+    /// Parse the headers of a part, starting at the line break ending the
+    /// delimiter line.
+    async fn headers(&mut self) -> Result<HeaderMap, MultipartError> {
+        let end = loop {
+            if let Some(end) = find(&self.buf[self.pos..], b"\r\n\r\n") {
+                break end;
+            }
+            if self.buf.len() - self.pos > MAX_HEADERS_SIZE {
+                return Err(MultipartError::Malformed("part headers too large"));
+            }
+            if !self.fill().await? {
+                return Err(MultipartError::Malformed("unexpected end of body"));
+            }
+        };
+
+        let mut headers = HeaderMap::new();
+        if end > 0 {
+            let block = &self.buf[self.pos + 2..self.pos + end];
+            for line in block.split(|&b| b == b'\n') {
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                let colon = line
+                    .iter()
+                    .position(|&b| b == b':')
+                    .ok_or(MultipartError::Malformed("invalid part header"))?;
+                let name = HeaderName::from_bytes(&line[..colon])
+                    .map_err(|_| MultipartError::Malformed("invalid part header name"))?;
+                let value = HeaderValue::from_bytes(line[colon + 1..].trim_ascii())
+                    .map_err(|_| MultipartError::Malformed("invalid part header value"))?;
+                headers.append(name, value);
+            }
+        }
+
+        self.pos += end + 4;
+        Ok(headers)
+    }
+}
+
+/// A part of a multipart body, its content is read with [`Field::chunk`] or
+/// one of the helpers built on it.
+pub struct Field<'a> {
+    multipart: &'a mut Multipart,
+    headers: HeaderMap,
+    name: Option<String>,
+    file_name: Option<String>,
+    read: usize,
+}
+
+impl<'a> Field<'a> {
+    fn new(multipart: &'a mut Multipart, headers: HeaderMap) -> Self {
+        let mut name = None;
+        let mut file_name = None;
+        if let Some(disposition) = headers.get(hyper::header::CONTENT_DISPOSITION) {
+            for (key, value) in header_params(&String::from_utf8_lossy(disposition.as_bytes())) {
+                match key.as_str() {
+                    "name" => name = Some(value),
+                    "filename" => file_name = Some(value),
+                    _ => {}
+                }
+            }
+        }
+
+        Field {
+            multipart,
+            headers,
+            name,
+            file_name,
+            read: 0,
+        }
+    }
+}
+
+impl Field<'_> {
+    /// The form field name.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The file name sent by the client for file uploads. It is not safe to
+    /// use as a path as is.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    pub fn content_type(&self) -> Option<&str> {
+        self.headers.get(hyper::header::CONTENT_TYPE)?.to_str().ok()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// The next piece of the content, `None` at its end.
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        let chunk = self.multipart.body_chunk().await?;
+        if let Some(chunk) = &chunk {
+            self.read += chunk.len();
+            if self.read > self.multipart.part_limit {
+                return Err(MultipartError::PartTooLarge {
+                    name: self.name.clone(),
+                    limit: self.multipart.part_limit,
+                });
+            }
+        }
+        Ok(chunk)
+    }
+
+    /// Read the whole content.
+    pub async fn bytes(&mut self) -> Result<Vec<u8>, MultipartError> {
+        let mut buf = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            buf.extend_from_slice(&chunk);
+        }
+        Ok(buf)
+    }
+
+    /// Read the whole content as UTF-8 text.
+    pub async fn text(&mut self) -> Result<String, MultipartError> {
+        String::from_utf8(self.bytes().await?)
+            .map_err(|_| MultipartError::Malformed("field is not valid UTF-8"))
+    }
+
+    /// Stream the content to a new file at `path`, returning its size. An
+    /// existing file is left alone and fails with
+    /// [`std::io::ErrorKind::AlreadyExists`], the new one is removed again if
+    /// the upload fails.
+    pub async fn save(&mut self, path: impl AsRef<Path>) -> Result<usize, MultipartError> {
+        let path = path.as_ref();
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .await
+            .map_err(MultipartError::Io)?;
+
+        let result = async {
+            let mut size = 0;
+            while let Some(chunk) = self.chunk().await? {
+                file.write_all(&chunk).await.map_err(MultipartError::Io)?;
+                size += chunk.len();
+            }
+            file.flush().await.map_err(MultipartError::Io)?;
+            Ok(size)
+        }
+        .await;
+
+        if result.is_err() {
+            drop(file);
+            let _ = tokio::fs::remove_file(path).await;
+        }
+        result
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The parameters of a header value such as `form-data; name="file"`, keys
+/// lowercased and quoted values unescaped.
+fn header_params(value: &str) -> Vec<(String, String)> {
+    let mut params = Vec::new();
+    let mut rest = value.find(';').map_or("", |i| &value[i..]);
+
+    loop {
+        rest = rest.trim_start_matches(&[';', ' ', '\t'][..]);
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => break,
+        };
+        let key = rest[..eq].trim().to_ascii_lowercase();
+        rest = rest[eq + 1..].trim_start();
+
+        let mut value = String::new();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut end = quoted.len();
+            let mut chars = quoted.char_indices().peekable();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    // only unescape `\"` and `\\`, browsers send windows
+                    // paths unescaped
+                    '\\' if matches!(chars.peek(), Some((_, '"')) | Some((_, '\\'))) => {
+                        value.extend(chars.next().map(|(_, c)| c));
+                    }
+                    c => value.push(c),
+                }
+            }
+            rest = &quoted[end..];
+        } else {
+            let end = rest.find(';').unwrap_or(rest.len());
+            value.push_str(rest[..end].trim());
+            rest = &rest[end..];
+        }
+
+        params.push((key, value));
+    }
+
+    params
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"preamble\r\n\
+        --b0undary\r\n\
+        Content-Disposition: form-data; name=\"title\"\r\n\
+        \r\n\
+        hello\r\n--b0undar\r\n\
+        --b0undary\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+        Content-Type: text/plain\r\n\
+        \r\n\
+        line 1\r\nline 2\r\n\
+        --b0undary\r\n\
+        \r\n\
+        no headers\r\n\
+        --b0undary--\r\n\
+        epilogue";
+
+    /// A body sent in chunks of `size` bytes.
+    fn body(data: &[u8], size: usize) -> hyper::Body {
+        let chunks: Vec<_> = data.chunks(size).map(Bytes::copy_from_slice).collect();
+        let (mut tx, body) = hyper::Body::channel();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                if tx.send_data(chunk).await.is_err() {
+                    break;
+                }
+            }
+        });
+        body
+    }
+
+    type Part = (Option<String>, Option<String>, Vec<u8>);
+
+    async fn parts(mut multipart: Multipart) -> Result<Vec<Part>, MultipartError> {
+        let mut parts = Vec::new();
+        while let Some(mut field) = multipart.next_field().await? {
+            let name = field.name().map(str::to_string);
+            let file_name = field.file_name().map(str::to_string);
+            parts.push((name, file_name, field.bytes().await?));
+        }
+        Ok(parts)
+    }
+
+    #[tokio::test]
+    async fn delimiters_split_across_chunks() {
+        let expected: Vec<Part> = vec![
+            (Some("title".into()), None, b"hello\r\n--b0undar".to_vec()),
+            (
+                Some("file".into()),
+                Some("a.txt".into()),
+                b"line 1\r\nline 2".to_vec(),
+            ),
+            (None, None, b"no headers".to_vec()),
+        ];
+
+        for size in [1, 2, 3, 5, 11, 13, 64, BODY.len()] {
+            let multipart = Multipart::new(body(BODY, size), "b0undary");
+            assert_eq!(
+                parts(multipart).await.unwrap(),
+                expected,
+                "chunks of {}",
+                size
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated_body() {
+        let end = BODY.len() - b"--\r\nepilogue".len();
+        for cut in [end - 1, 40, 20] {
+            let multipart = Multipart::new(body(&BODY[..cut], 7), "b0undary");
+            assert!(
+                matches!(parts(multipart).await, Err(MultipartError::Malformed(_))),
+                "cut at {}",
+                cut
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn total_limit() {
+        let mut multipart = Multipart::new(body(BODY, 16), "b0undary");
+        multipart.total_limit(BODY.len());
+        assert!(parts(multipart).await.is_ok());
+
+        let mut multipart = Multipart::new(body(BODY, 16), "b0undary");
+        multipart.total_limit(BODY.len() - 1);
+        assert!(matches!(
+            parts(multipart).await,
+            Err(MultipartError::TooLarge { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn part_limit() {
+        let mut multipart = Multipart::new(body(BODY, 16), "b0undary");
+        multipart.part_limit(b"hello\r\n--b0undar".len());
+        assert!(parts(multipart).await.is_ok());
+
+        let mut multipart = Multipart::new(body(BODY, 16), "b0undary");
+        multipart.part_limit(15);
+        match parts(multipart).await {
+            Err(MultipartError::PartTooLarge { name, limit }) => {
+                assert_eq!(name.as_deref(), Some("title"));
+                assert_eq!(limit, 15);
+            }
+            other => panic!("expected PartTooLarge, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn save_keeps_existing_files() {
+        let dir = std::env::temp_dir();
+        let path = dir.join(format!("tinyweb-save-{}", std::process::id()));
+        std::fs::write(&path, "keep me").unwrap();
+
+        let mut multipart = Multipart::new(body(BODY, 16), "b0undary");
+        let mut field = multipart.next_field().await.unwrap().unwrap();
+        let result = field.save(&path).await;
+        let content = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+
+        match result {
+            Err(MultipartError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::AlreadyExists)
+            }
+            other => panic!("expected AlreadyExists, got {:?}", other),
+        }
+        assert_eq!(content.unwrap(), "keep me");
+
+        // a failed upload removes the file it created
+        let mut multipart = Multipart::new(body(BODY, 16), "b0undary");
+        multipart.part_limit(3);
+        let mut field = multipart.next_field().await.unwrap().unwrap();
+        assert!(field.save(&path).await.is_err());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn quoted_header_params() {
+        assert_eq!(
+            header_params(r#"form-data; NAME="a\"b;c"; filename="C:\dir\f.txt""#),
+            vec![
+                ("name".to_string(), "a\"b;c".to_string()),
+                ("filename".to_string(), r"C:\dir\f.txt".to_string()),
+            ]
+        );
+        assert_eq!(
+            header_params(r#"form-data; name="a\\b"; size = 3"#),
+            vec![
+                ("name".to_string(), r"a\b".to_string()),
+                ("size".to_string(), "3".to_string()),
+            ]
+        );
+        assert_eq!(header_params("form-data"), vec![]);
+    }
+
+    #[test]
+    fn boundary() {
+        assert_eq!(
+            Multipart::boundary(r#"multipart/form-data; boundary="x y""#).as_deref(),
+            Some("x y")
+        );
+        assert_eq!(
+            Multipart::boundary("Multipart/Form-Data; boundary=abc").as_deref(),
+            Some("abc")
+        );
+        assert_eq!(Multipart::boundary("multipart/form-data"), None);
+        assert_eq!(Multipart::boundary("text/plain; boundary=abc"), None);
+    }
+}
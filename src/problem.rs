@@ -0,0 +1,205 @@
+//! Problem details for HTTP APIs, RFC 7807.
+
+use hyper::StatusCode;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use serde_json::{Map, Value};
+
+use crate::{Error, IntoResponse, Response};
+
+/// The standard members, which extension members cannot replace.
+const MEMBERS: [&str; 5] = ["type", "title", "status", "detail", "instance"];
+
+/// An error rendered as `application/problem+json`, e.g.
+///
+/// ```no_run
+/// # use hyper::StatusCode;
+/// # use tinyweb::Problem;
+/// # let _ =
+/// Problem::new(StatusCode::FORBIDDEN)
+///     .with_type("https://example.com/probs/out-of-credit")
+///     .with_title("You do not have enough credit.")
+///     .with_detail("Your current balance is 30, but that costs 50.")
+///     .with_extension("balance", 30)
+/// # ;
+/// ```
+///
+/// To answer all errors with problem details:
+///
+/// ```no_run
+/// # use tinyweb::{Error, Problem, Server};
+/// # let mut srv = Server::new();
+/// srv.error_handler(|err: Error| {
+///     if err.status().is_server_error() {
+///         eprintln!("{:#}", err);
+///     }
+///     Problem::from(err)
+/// });
+/// ```
+#[derive(Debug, Clone)]
+pub struct Problem {
+    status: StatusCode,
+    kind: String,
+    title: Option<String>,
+    detail: Option<String>,
+    instance: Option<String>,
+    extensions: Map<String, Value>,
+}
+
+impl Problem {
+    /// A problem of type `about:blank`, titled after `status`.
+    pub fn new(status: StatusCode) -> Self {
+        Problem {
+            status,
+            kind: "about:blank".to_string(),
+            title: status.canonical_reason().map(str::to_string),
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    /// A URI identifying the problem type.
+    pub fn with_type(mut self, uri: impl ToString) -> Self {
+        self.kind = uri.to_string();
+        self
+    }
+
+    /// A short summary of the problem type.
+    pub fn with_title(mut self, title: impl ToString) -> Self {
+        self.title = Some(title.to_string());
+        self
+    }
+
+    /// An explanation specific to this occurrence of the problem.
+    pub fn with_detail(mut self, detail: impl ToString) -> Self {
+        self.detail = Some(detail.to_string());
+        self
+    }
+
+    /// A URI identifying this occurrence of the problem.
+    pub fn with_instance(mut self, uri: impl ToString) -> Self {
+        self.instance = Some(uri.to_string());
+        self
+    }
+
+    /// Add a member specific to the problem type. Names of the standard
+    /// members are ignored.
+    pub fn with_extension(mut self, name: impl ToString, value: impl Into<Value>) -> Self {
+        let name = name.to_string();
+        if !MEMBERS.contains(&name.as_str()) {
+            self.extensions.insert(name, value.into());
+        }
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// The status of the error and its message as the detail, except for server
+/// errors whose message is not exposed to clients.
+impl From<Error> for Problem {
+    fn from(err: Error) -> Self {
+        let problem = Problem::new(err.status());
+        if err.status().is_server_error() {
+            problem
+        } else {
+            problem.with_detail(err.message())
+        }
+    }
+}
+
+impl Serialize for Problem {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("type", &self.kind)?;
+        if let Some(title) = &self.title {
+            map.serialize_entry("title", title)?;
+        }
+        map.serialize_entry("status", &self.status.as_u16())?;
+        if let Some(detail) = &self.detail {
+            map.serialize_entry("detail", detail)?;
+        }
+        if let Some(instance) = &self.instance {
+            map.serialize_entry("instance", instance)?;
+        }
+        for (name, value) in &self.extensions {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let mut resp = crate::ResponseBuiler::with_json(&self);
+        if resp.status().is_success() {
+            *resp.status_mut() = self.status;
+            resp.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/problem+json"),
+            );
+        }
+        resp
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn members() {
+        let problem = Problem::new(StatusCode::FORBIDDEN)
+            .with_type("https://example.com/probs/out-of-credit")
+            .with_detail("Your current balance is 30, but that costs 50.")
+            .with_instance("/account/12345")
+            .with_extension("balance", 30)
+            .with_extension("status", 200);
+
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            serde_json::json!({
+                "type": "https://example.com/probs/out-of-credit",
+                "title": "Forbidden",
+                "status": 403,
+                "detail": "Your current balance is 30, but that costs 50.",
+                "instance": "/account/12345",
+                "balance": 30,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn from_errors() {
+        let problem = Problem::from(Error::new("no such user").with_status(StatusCode::NOT_FOUND));
+        let resp = problem.into_response();
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            resp.headers()[hyper::header::CONTENT_TYPE],
+            "application/problem+json"
+        );
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "no such user",
+            })
+        );
+
+        // server errors keep their message to themselves
+        let problem = Problem::from(Error::new("db password=hunter2 failed"));
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            serde_json::json!({
+                "type": "about:blank",
+                "title": "Internal Server Error",
+                "status": 500,
+            })
+        );
+    }
+}
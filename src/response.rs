@@ -0,0 +1,154 @@
+//! Conversion of handler return values into responses.
+
+use std::convert::{Infallible, TryFrom};
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use serde::Serialize;
+
+use crate::{Json, Response, ResponseBuiler};
+
+/// A value a handler can return, e.g.
+///
+/// ```no_run
+/// # use hyper::StatusCode;
+/// # use tinyweb::Json;
+/// # #[derive(serde::Deserialize)]
+/// # struct NewUser {}
+/// # #[derive(serde::Serialize)]
+/// # struct User {}
+/// # async fn insert(_user: NewUser) -> User { User {} }
+/// async fn create(Json(user): Json<NewUser>) -> (StatusCode, Json<User>) {
+///     (StatusCode::CREATED, Json(insert(user).await))
+/// }
+/// # tinyweb::Server::new().post("/users", create);
+/// ```
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for Infallible {
+    fn into_response(self) -> Response {
+        match self {}
+    }
+}
+
+/// `text/plain`
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        ResponseBuiler::with_text(self)
+    }
+}
+
+/// `text/plain`
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        ResponseBuiler::with_text(self)
+    }
+}
+
+/// An empty response with this status.
+impl IntoResponse for StatusCode {
+    fn into_response(self) -> Response {
+        ResponseBuiler::with_status(self)
+    }
+}
+
+impl<T: IntoResponse, E: IntoResponse> IntoResponse for Result<T, E> {
+    fn into_response(self) -> Response {
+        match self {
+            Ok(value) => value.into_response(),
+            Err(err) => err.into_response(),
+        }
+    }
+}
+
+/// Override the status of `T`.
+impl<T: IntoResponse> IntoResponse for (StatusCode, T) {
+    fn into_response(self) -> Response {
+        let (status, body) = self;
+        let mut resp = body.into_response();
+        *resp.status_mut() = status;
+        resp
+    }
+}
+
+/// Add headers to `T`, replacing the ones of the same name.
+impl<T: IntoResponse> IntoResponse for (HeaderMap, T) {
+    fn into_response(self) -> Response {
+        let (headers, body) = self;
+        let mut resp = body.into_response();
+        resp.headers_mut().extend(headers);
+        resp
+    }
+}
+
+/// Add headers to `T`, replacing the ones of the same name, e.g.
+/// `([("cache-control", "no-store")], body)`. Invalid headers make it a `500
+/// Internal Server Error`.
+impl<K, V, T, const N: usize> IntoResponse for ([(K, V); N], T)
+where
+    HeaderName: TryFrom<K>,
+    HeaderValue: TryFrom<V>,
+    T: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let (pairs, body) = self;
+        let mut headers = HeaderMap::with_capacity(N);
+        for (name, value) in pairs {
+            match (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+                (Ok(name), Ok(value)) => {
+                    headers.append(name, value);
+                }
+                _ => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
+
+        (headers, body).into_response()
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (StatusCode, HeaderMap, T) {
+    fn into_response(self) -> Response {
+        let (status, headers, body) = self;
+        (status, (headers, body)).into_response()
+    }
+}
+
+impl<K, V, T, const N: usize> IntoResponse for (StatusCode, [(K, V); N], T)
+where
+    HeaderName: TryFrom<K>,
+    HeaderValue: TryFrom<V>,
+    T: IntoResponse,
+{
+    fn into_response(self) -> Response {
+        let (status, headers, body) = self;
+        (status, (headers, body)).into_response()
+    }
+}
+
+/// See [`ResponseBuiler::with_json`].
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        ResponseBuiler::with_json(&self.0)
+    }
+}
+
+/// An HTML response body.
+#[derive(Debug, Clone)]
+pub struct Html<T>(pub T);
+
+impl<T: Into<hyper::Body>> IntoResponse for Html<T> {
+    fn into_response(self) -> Response {
+        hyper::Response::builder()
+            .header(hyper::header::CONTENT_TYPE, "text/html; charset=UTF-8")
+            .body(self.0.into())
+            .unwrap()
+    }
+}
@@ -0,0 +1,711 @@
+//! A radix tree router keyed by path, with method indexed slots at every node.
+//!
+//! Matching walks the tree trying static children first, then parameters and
+//! wildcards, backtracking when a branch has no acceptable value. Parameters
+//! are captured as byte ranges of the path, no map is built while routing.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use hyper::http::uri::PathAndQuery;
+use hyper::Method;
+
+/// The most parameters a pattern can capture.
+pub(crate) const MAX_PARAMS: usize = 16;
+
+/// A segment of a route pattern.
+pub(crate) enum Segment<'p> {
+    Static(&'p str),
+    /// `:name`, `{name}` or `{name:constraint}`
+    Param(&'p str, Option<&'p str>),
+    /// `*name`
+    Wildcard(&'p str),
+}
+
+impl<'p> Segment<'p> {
+    pub(crate) fn parse(segment: &'p str) -> Self {
+        if let Some(name) = segment.strip_prefix(':') {
+            Segment::Param(name, None)
+        } else if let Some(name) = segment.strip_prefix('*') {
+            Segment::Wildcard(name)
+        } else if segment.len() > 1 && segment.starts_with('{') && segment.ends_with('}') {
+            let inner = &segment[1..segment.len() - 1];
+            match inner.find(':') {
+                Some(i) => Segment::Param(&inner[..i], Some(&inner[i + 1..])),
+                None => Segment::Param(inner, None),
+            }
+        } else {
+            Segment::Static(segment)
+        }
+    }
+}
+
+impl Segment<'_> {
+    pub(crate) fn constraint(&self) -> Option<&str> {
+        match self {
+            Segment::Param(_, constraint) => *constraint,
+            _ => None,
+        }
+    }
+
+    /// Whether two patterns are the same apart from parameter names and
+    /// constraints.
+    pub(crate) fn same_shape(a: &[Segment], b: &[Segment]) -> bool {
+        a.len() == b.len()
+            && a.iter().zip(b).all(|pair| match pair {
+                (Segment::Static(x), Segment::Static(y)) => x == y,
+                (Segment::Param(..), Segment::Param(..)) => true,
+                (Segment::Wildcard(_), Segment::Wildcard(_)) => true,
+                _ => false,
+            })
+    }
+
+    /// Whether some path matches both patterns.
+    pub(crate) fn overlaps(a: &[Segment], b: &[Segment]) -> bool {
+        match (a.split_first(), b.split_first()) {
+            (None, None) => true,
+            (None, _) | (_, None) => false,
+            (Some((Segment::Wildcard(_), _)), Some((other, _)))
+            | (Some((other, _)), Some((Segment::Wildcard(_), _))) => {
+                !matches!(other, Segment::Static(""))
+            }
+            (Some((x, a)), Some((y, b))) => {
+                let compatible = match (x, y) {
+                    (Segment::Static(x), Segment::Static(y)) => x == y,
+                    (Segment::Static(s), _) | (_, Segment::Static(s)) => !s.is_empty(),
+                    _ => true,
+                };
+                compatible && Self::overlaps(a, b)
+            }
+        }
+    }
+
+    /// The specificity of a pattern: the number of static, parameter and
+    /// wildcard segments.
+    pub(crate) fn rank(segments: &[Segment]) -> (usize, usize, usize) {
+        segments.iter().fold(
+            (0, 0, 0),
+            |(statics, params, stars), segment| match segment {
+                Segment::Static(_) => (statics + 1, params, stars),
+                Segment::Param(..) => (statics, params + 1, stars),
+                Segment::Wildcard(_) => (statics, params, stars + 1),
+            },
+        )
+    }
+}
+
+/// Restricts the values a path parameter matches, either a type name such as
+/// `u64` or a regex which has to match the whole segment.
+pub(crate) enum Constraint {
+    Parse(fn(&str) -> bool),
+    Regex(regex::Regex),
+}
+
+fn parses<T: FromStr>(value: &str) -> bool {
+    value.parse::<T>().is_ok()
+}
+
+impl Constraint {
+    pub(crate) fn new(spec: &str) -> Result<Self, regex::Error> {
+        let parse: fn(&str) -> bool = match spec {
+            "u8" => parses::<u8>,
+            "u16" => parses::<u16>,
+            "u32" => parses::<u32>,
+            "u64" => parses::<u64>,
+            "u128" => parses::<u128>,
+            "usize" => parses::<usize>,
+            "i8" => parses::<i8>,
+            "i16" => parses::<i16>,
+            "i32" => parses::<i32>,
+            "i64" => parses::<i64>,
+            "i128" => parses::<i128>,
+            "isize" => parses::<isize>,
+            "f32" => parses::<f32>,
+            "f64" => parses::<f64>,
+            "bool" => parses::<bool>,
+            _ => return regex::Regex::new(&format!("^(?:{})$", spec)).map(Constraint::Regex),
+        };
+
+        Ok(Constraint::Parse(parse))
+    }
+
+    pub(crate) fn matches(&self, value: &str) -> bool {
+        match self {
+            Constraint::Parse(parse) => parse(value),
+            Constraint::Regex(re) => re.is_match(value),
+        }
+    }
+}
+
+/// A label of a host pattern.
+enum HostLabel {
+    Static(String),
+    Param(Option<Constraint>),
+    Any,
+}
+
+/// Host pattern such as `{tenant}.example.com`, every `{name}` or `*` label
+/// matches exactly one label of the requested host.
+pub(crate) struct HostPattern {
+    labels: Vec<HostLabel>,
+    names: Arc<[String]>,
+}
+
+impl HostPattern {
+    pub(crate) fn parse(pattern: &str) -> Result<Self, String> {
+        let mut labels = Vec::new();
+        let mut names = Vec::new();
+
+        for label in pattern.split('.') {
+            let label = match Segment::parse(label) {
+                Segment::Static(label) => HostLabel::Static(label.to_string()),
+                Segment::Wildcard("") => HostLabel::Any,
+                Segment::Wildcard(name) | Segment::Param(name, None) => {
+                    names.push(name.to_string());
+                    HostLabel::Param(None)
+                }
+                Segment::Param(name, Some(spec)) => {
+                    let constraint = Constraint::new(spec)
+                        .map_err(|e| format!("invalid constraint for {:?}: {}", name, e))?;
+                    names.push(name.to_string());
+                    HostLabel::Param(Some(constraint))
+                }
+            };
+            labels.push(label);
+        }
+
+        if names.len() > MAX_PARAMS {
+            return Err(format!("more than {} parameters", MAX_PARAMS));
+        }
+
+        Ok(HostPattern {
+            labels,
+            names: names.into(),
+        })
+    }
+
+    /// Match `host` (without port), capturing the parameter labels.
+    pub(crate) fn matches(&self, host: &str) -> Option<Captures> {
+        let mut captures = Captures::default();
+        let mut labels = host.split('.');
+        let mut start = 0;
+
+        for pattern in &self.labels {
+            let label = labels.next()?;
+            match pattern {
+                HostLabel::Static(s) if s.eq_ignore_ascii_case(label) => {}
+                HostLabel::Static(_) => return None,
+                HostLabel::Param(Some(constraint)) if !constraint.matches(label) => return None,
+                HostLabel::Param(_) => {
+                    captures.push(start, start + label.len());
+                }
+                HostLabel::Any => {}
+            }
+            start += label.len() + 1;
+        }
+
+        if labels.next().is_some() {
+            return None;
+        }
+        Some(captures)
+    }
+
+    /// The parameters of a host matched by this pattern.
+    pub(crate) fn params(&self, host: &str, captures: Captures) -> Params {
+        Params::new(Source::Text(host.to_string()), self.names.clone(), captures)
+    }
+}
+
+/// Byte ranges of the captured parameters, in pattern order.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Captures {
+    ranges: [(u32, u32); MAX_PARAMS],
+    len: usize,
+}
+
+impl Captures {
+    fn push(&mut self, start: usize, end: usize) -> bool {
+        if self.len == MAX_PARAMS {
+            return false;
+        }
+        self.ranges[self.len] = (start as u32, end as u32);
+        self.len += 1;
+        true
+    }
+
+    fn pop(&mut self) {
+        self.len -= 1;
+    }
+
+    /// The `i`th captured value of `text`.
+    pub(crate) fn get<'t>(&self, text: &'t str, i: usize) -> Option<&'t str> {
+        if i >= self.len {
+            return None;
+        }
+        let (start, end) = self.ranges[i];
+        text.get(start as usize..end as usize)
+    }
+}
+
+/// The text parameters were captured from.
+#[derive(Clone, Default)]
+pub(crate) enum Source {
+    #[default]
+    Empty,
+    /// The request path, sharing the buffer of the request uri.
+    Path(PathAndQuery),
+    Text(String),
+}
+
+impl Source {
+    fn as_str(&self) -> &str {
+        match self {
+            Source::Empty => "",
+            Source::Path(path) => path.path(),
+            Source::Text(text) => text,
+        }
+    }
+}
+
+/// Parameters captured from the request path or host.
+#[derive(Clone, Default)]
+pub struct Params {
+    source: Source,
+    names: Option<Arc<[String]>>,
+    captures: Captures,
+}
+
+impl Params {
+    pub(crate) fn new(source: Source, names: Arc<[String]>, captures: Captures) -> Self {
+        Params {
+            source,
+            names: Some(names),
+            captures,
+        }
+    }
+
+    pub fn find(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Iterate over the parameters as `(name, value)`, in pattern order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        let text = self.source.as_str();
+        self.names
+            .iter()
+            .flat_map(|names| names.iter().enumerate())
+            .filter(|(_, name)| !name.is_empty())
+            .filter_map(move |(i, name)| Some((name.as_str(), self.captures.get(text, i)?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+/// Values indexed by request method, without hashing for the standard ones.
+pub(crate) struct MethodMap<T> {
+    standard: [Option<T>; 9],
+    extensions: Vec<(Method, T)>,
+}
+
+const STANDARD_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::CONNECT,
+    Method::OPTIONS,
+    Method::TRACE,
+    Method::PATCH,
+];
+
+fn standard_index(method: &Method) -> Option<usize> {
+    let index = match method.as_str() {
+        "GET" => 0,
+        "HEAD" => 1,
+        "POST" => 2,
+        "PUT" => 3,
+        "DELETE" => 4,
+        "CONNECT" => 5,
+        "OPTIONS" => 6,
+        "TRACE" => 7,
+        "PATCH" => 8,
+        _ => return None,
+    };
+    Some(index)
+}
+
+impl<T> MethodMap<T> {
+    pub(crate) fn get(&self, method: &Method) -> Option<&T> {
+        match standard_index(method) {
+            Some(i) => self.standard[i].as_ref(),
+            None => self
+                .extensions
+                .iter()
+                .find(|(m, _)| m == method)
+                .map(|(_, value)| value),
+        }
+    }
+
+    pub(crate) fn entry(&mut self, method: Method) -> &mut T
+    where
+        T: Default,
+    {
+        if let Some(i) = standard_index(&method) {
+            return self.standard[i].get_or_insert_with(T::default);
+        }
+
+        match self.extensions.iter().position(|(m, _)| *m == method) {
+            Some(i) => &mut self.extensions[i].1,
+            None => {
+                self.extensions.push((method, T::default()));
+                &mut self.extensions.last_mut().unwrap().1
+            }
+        }
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&Method, &T)> {
+        STANDARD_METHODS
+            .iter()
+            .zip(&self.standard)
+            .filter_map(|(method, value)| Some((method, value.as_ref()?)))
+            .chain(
+                self.extensions
+                    .iter()
+                    .map(|(method, value)| (method, value)),
+            )
+    }
+}
+
+impl<T> Default for MethodMap<T> {
+    fn default() -> Self {
+        MethodMap {
+            standard: Default::default(),
+            extensions: Vec::new(),
+        }
+    }
+}
+
+/// Edges are labelled with bytes rather than chars, so that children are
+/// told apart by the first byte of their prefix and a split never falls
+/// before it.
+struct Node<T> {
+    prefix: Vec<u8>,
+    statics: Vec<Node<T>>,
+    param: Option<Box<Node<T>>>,
+    wildcard: Option<Box<Node<T>>>,
+    value: Option<T>,
+}
+
+impl<T> Node<T> {
+    fn new(prefix: &[u8]) -> Self {
+        Node {
+            prefix: prefix.to_vec(),
+            statics: Vec::new(),
+            param: None,
+            wildcard: None,
+            value: None,
+        }
+    }
+
+    /// The node reached after the static text `s`, splitting edges as needed.
+    fn insert_static(&mut self, s: &[u8]) -> &mut Node<T> {
+        let first = match s.first() {
+            Some(&first) => first,
+            None => return self,
+        };
+
+        match self
+            .statics
+            .iter()
+            .position(|child| child.prefix[0] == first)
+        {
+            Some(i) => {
+                let child = &mut self.statics[i];
+                // at least the first byte is shared
+                let common = common_prefix(&child.prefix, s);
+                if common < child.prefix.len() {
+                    child.split(common);
+                }
+                child.insert_static(&s[common..])
+            }
+            None => {
+                self.statics.push(Node::new(s));
+                self.statics.last_mut().unwrap()
+            }
+        }
+    }
+
+    /// Split the prefix at `at`, moving everything below into a new child.
+    fn split(&mut self, at: usize) {
+        let child = Node {
+            prefix: self.prefix[at..].to_vec(),
+            statics: std::mem::take(&mut self.statics),
+            param: self.param.take(),
+            wildcard: self.wildcard.take(),
+            value: self.value.take(),
+        };
+        self.prefix.truncate(at);
+        self.statics = vec![child];
+    }
+
+    fn find<'r, R, F>(
+        &'r self,
+        path: &[u8],
+        full_len: usize,
+        captures: &mut Captures,
+        accept: &mut F,
+    ) -> Option<R>
+    where
+        F: FnMut(&'r T, &Captures) -> Option<R>,
+    {
+        let rest = path.strip_prefix(self.prefix.as_slice())?;
+        if rest.is_empty() {
+            return self
+                .value
+                .as_ref()
+                .and_then(|value| accept(value, captures));
+        }
+
+        let first = rest[0];
+        if let Some(child) = self.statics.iter().find(|child| child.prefix[0] == first) {
+            if let Some(found) = child.find(rest, full_len, captures, accept) {
+                return Some(found);
+            }
+        }
+
+        let start = full_len - rest.len();
+
+        if let Some(param) = &self.param {
+            let end = rest.iter().position(|&b| b == b'/').unwrap_or(rest.len());
+            if end > 0 && captures.push(start, start + end) {
+                if let Some(found) = param.find(&rest[end..], full_len, captures, accept) {
+                    return Some(found);
+                }
+                captures.pop();
+            }
+        }
+
+        if let Some(wildcard) = &self.wildcard {
+            if captures.push(start, full_len) {
+                if let Some(found) = wildcard
+                    .value
+                    .as_ref()
+                    .and_then(|value| accept(value, captures))
+                {
+                    return Some(found);
+                }
+                captures.pop();
+            }
+        }
+
+        None
+    }
+}
+
+/// Length of the common prefix of `a` and `b`.
+fn common_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// Radix tree from path patterns to values.
+pub(crate) struct Router<T> {
+    root: Node<T>,
+}
+
+impl<T> Router<T> {
+    pub(crate) fn new() -> Self {
+        Router {
+            root: Node::new(b""),
+        }
+    }
+
+    /// The value for `pattern`, inserting a default one. A leading `/` is
+    /// implied, wildcards have to be the last segment.
+    pub(crate) fn entry(&mut self, pattern: &str) -> Result<&mut T, String>
+    where
+        T: Default,
+    {
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        let segments: Vec<_> = pattern.split('/').collect();
+
+        let mut node = &mut self.root;
+        let mut pending = String::from("/");
+        let mut params = 0;
+
+        for (i, segment) in segments.iter().enumerate() {
+            if i > 0 {
+                pending.push('/');
+            }
+
+            match Segment::parse(segment) {
+                Segment::Static(segment) => pending.push_str(segment),
+                Segment::Param(..) => {
+                    node = node.insert_static(pending.as_bytes());
+                    pending.clear();
+                    node = node.param.get_or_insert_with(|| Box::new(Node::new(b"")));
+                    params += 1;
+                }
+                Segment::Wildcard(_) => {
+                    if i + 1 != segments.len() {
+                        return Err("a wildcard has to be the last segment".to_string());
+                    }
+                    node = node.insert_static(pending.as_bytes());
+                    pending.clear();
+                    node = node
+                        .wildcard
+                        .get_or_insert_with(|| Box::new(Node::new(b"")));
+                    params += 1;
+                }
+            }
+        }
+
+        if params > MAX_PARAMS {
+            return Err(format!("more than {} parameters", MAX_PARAMS));
+        }
+
+        node = node.insert_static(pending.as_bytes());
+        Ok(node.value.get_or_insert_with(T::default))
+    }
+
+    /// Find the most specific value matching `path` which `accept` takes,
+    /// static segments win over parameters and parameters over wildcards.
+    pub(crate) fn find<'r, R, F>(&'r self, path: &str, mut accept: F) -> Option<R>
+    where
+        F: FnMut(&'r T, &Captures) -> Option<R>,
+    {
+        let mut captures = Captures::default();
+        self.root
+            .find(path.as_bytes(), path.len(), &mut captures, &mut accept)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router(patterns: &[&'static str]) -> Router<Vec<&'static str>> {
+        let mut router = Router::<Vec<_>>::new();
+        for pattern in patterns {
+            router.entry(pattern).unwrap().push(*pattern);
+        }
+        router
+    }
+
+    /// The pattern matching `path` and the captured values.
+    fn find(router: &Router<Vec<&'static str>>, path: &str) -> Option<(&'static str, Vec<String>)> {
+        router.find(path, |value, captures| {
+            let values = (0..)
+                .map_while(|i| captures.get(path, i))
+                .map(str::to_string)
+                .collect();
+            Some((value[0], values))
+        })
+    }
+
+    #[test]
+    fn static_params_and_wildcards() {
+        let router = router(&[
+            "/",
+            "/users",
+            "/users/:id",
+            "/users/:id/posts",
+            "/files/*path",
+        ]);
+
+        assert_eq!(find(&router, "/"), Some(("/", vec![])));
+        assert_eq!(find(&router, "/users"), Some(("/users", vec![])));
+        assert_eq!(
+            find(&router, "/users/42"),
+            Some(("/users/:id", vec!["42".into()]))
+        );
+        assert_eq!(
+            find(&router, "/users/42/posts"),
+            Some(("/users/:id/posts", vec!["42".into()]))
+        );
+        assert_eq!(
+            find(&router, "/files/a/b.txt"),
+            Some(("/files/*path", vec!["a/b.txt".into()]))
+        );
+        assert_eq!(find(&router, "/users/"), None);
+        assert_eq!(find(&router, "/users/42/comments"), None);
+    }
+
+    #[test]
+    fn splits_shared_prefixes() {
+        let router = router(&["/teams", "/team", "/tea", "/te/:x"]);
+
+        assert_eq!(find(&router, "/teams"), Some(("/teams", vec![])));
+        assert_eq!(find(&router, "/team"), Some(("/team", vec![])));
+        assert_eq!(find(&router, "/tea"), Some(("/tea", vec![])));
+        assert_eq!(find(&router, "/te/a"), Some(("/te/:x", vec!["a".into()])));
+        assert_eq!(find(&router, "/te"), None);
+        assert_eq!(find(&router, "/teamsx"), None);
+    }
+
+    #[test]
+    fn splits_inside_multibyte_chars() {
+        // é and ç, 日 and 曜 share their first byte
+        let router = router(&["/café", "/caç", "/ca", "/日本/:x", "/日曜"]);
+
+        assert_eq!(find(&router, "/café"), Some(("/café", vec![])));
+        assert_eq!(find(&router, "/caç"), Some(("/caç", vec![])));
+        assert_eq!(find(&router, "/ca"), Some(("/ca", vec![])));
+        assert_eq!(
+            find(&router, "/日本/é"),
+            Some(("/日本/:x", vec!["é".into()]))
+        );
+        assert_eq!(find(&router, "/日曜"), Some(("/日曜", vec![])));
+        assert_eq!(find(&router, "/caf"), None);
+        assert_eq!(find(&router, "/日"), None);
+    }
+
+    #[test]
+    fn backtracks_to_params_and_wildcards() {
+        let router = router(&["/a/b/c", "/a/:x/d", "/a/*rest"]);
+
+        assert_eq!(find(&router, "/a/b/c"), Some(("/a/b/c", vec![])));
+        assert_eq!(find(&router, "/a/b/d"), Some(("/a/:x/d", vec!["b".into()])));
+        assert_eq!(
+            find(&router, "/a/b/e"),
+            Some(("/a/*rest", vec!["b/e".into()]))
+        );
+    }
+
+    #[test]
+    fn backtracks_past_rejected_values() {
+        let router = router(&["/users/new", "/users/:id"]);
+
+        let found = router.find("/users/new", |value, _| match value[0] {
+            "/users/new" => None,
+            pattern => Some(pattern),
+        });
+        assert_eq!(found, Some("/users/:id"));
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        let mut router = Router::<Vec<&str>>::new();
+
+        assert!(router.entry("/files/*path/more").is_err());
+        let too_many = (0..=MAX_PARAMS)
+            .map(|i| format!("/:p{}", i))
+            .collect::<String>();
+        assert!(router.entry(&too_many).is_err());
+    }
+}